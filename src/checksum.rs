@@ -0,0 +1,134 @@
+use clap::ValueEnum;
+use sha2::Digest;
+use sha2::Sha256;
+use std::fs::File;
+use std::io;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+use xxhash_rust::xxh3::Xxh3;
+
+#[derive(Clone, Copy, PartialEq, Debug, Default, ValueEnum)]
+pub enum Checksum {
+    /// BLAKE3 (fast cryptographic hash).
+    #[default]
+    Blake3,
+    /// SHA-256 (slower cryptographic hash).
+    Sha256,
+    /// XXH3 (fastest non-cryptographic hash).
+    Xxh3,
+}
+
+enum Hasher {
+    Blake3(Box<blake3::Hasher>),
+    Sha256(Sha256),
+    Xxh3(Box<Xxh3>),
+}
+
+impl Hasher {
+    fn new(checksum: Checksum) -> Self {
+        match checksum {
+            Checksum::Blake3 => Hasher::Blake3(Box::default()),
+            Checksum::Sha256 => Hasher::Sha256(Sha256::new()),
+            Checksum::Xxh3 => Hasher::Xxh3(Box::default()),
+        }
+    }
+
+    fn finish(self) -> Vec<u8> {
+        match self {
+            Hasher::Blake3(hasher) => hasher.finalize().as_bytes().to_vec(),
+            Hasher::Sha256(hasher) => hasher.finalize().to_vec(),
+            Hasher::Xxh3(hasher) => hasher.digest().to_be_bytes().to_vec(),
+        }
+    }
+}
+
+impl Write for Hasher {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Hasher::Blake3(hasher) => {
+                hasher.update(buf);
+            }
+            Hasher::Sha256(hasher) => hasher.update(buf),
+            Hasher::Xxh3(hasher) => hasher.update(buf),
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Writes data to the inner writer and computes their checksum.
+struct HashWriter<'a, W> {
+    inner: &'a mut W,
+    hasher: Hasher,
+}
+
+impl<W: Write> Write for HashWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.write_all(&buf[..written])?;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// Content is copied through user space, so the source is read only once.
+pub fn copy(src: &mut File, dst: &mut File, checksum: Checksum) -> io::Result<Vec<u8>> {
+    let mut writer = HashWriter {
+        inner: dst,
+        hasher: Hasher::new(checksum),
+    };
+    io::copy(src, &mut writer)?;
+    Ok(writer.hasher.finish())
+}
+
+pub fn compute(file: &mut File, checksum: Checksum) -> io::Result<Vec<u8>> {
+    let mut hasher = Hasher::new(checksum);
+    file.seek(SeekFrom::Start(0))?;
+    io::copy(file, &mut hasher)?;
+    Ok(hasher.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::prelude::*;
+    use assert_fs::NamedTempFile;
+    use claim::assert_ok;
+    use std::fs::OpenOptions;
+    use test_case::test_case;
+
+    #[test_case(Checksum::Blake3, "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85" ; "blake3")]
+    #[test_case(Checksum::Sha256, "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad" ; "sha256")]
+    #[test_case(Checksum::Xxh3,   "78af5f94892f3950"                                                 ; "xxh3")]
+    fn copy_and_compute(checksum: Checksum, expected: &str) {
+        let src_file = assert_ok!(NamedTempFile::new("a"));
+        let dst_file = assert_ok!(NamedTempFile::new("b"));
+        assert_ok!(src_file.write_str("abc"));
+
+        let mut src = assert_ok!(File::open(src_file.path()));
+        let mut dst = assert_ok!(OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .read(true)
+            .write(true)
+            .open(dst_file.path()));
+
+        assert_eq!(
+            hex(&assert_ok!(copy(&mut src, &mut dst, checksum))),
+            expected
+        );
+        assert_eq!(hex(&assert_ok!(compute(&mut dst, checksum))), expected);
+        dst_file.assert("abc");
+    }
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+}
@@ -1,4 +1,11 @@
+use crate::checksum::Checksum;
+use crate::fs::Attribute;
+use crate::fs::OverwriteMode;
+use crate::fs::ReflinkMode;
+use crate::fs::SparseMode;
+use crate::fs::SpecialType;
 use clap::Parser;
+use std::path::PathBuf;
 
 const MANUAL: &str = "
 Example input:
@@ -21,28 +28,143 @@ Input format:
      `>` is followed by an output path.
   3. Paths may be absolute or relative.
      Relative paths a resolved to the current working directory.
-  4. Input path must be an existing file or directory.
-     Output path may not exist.
+  4. Input path must be an existing file, directory or symbolic link
+     (which may be dangling). Output path may not exist.
+     Output path must not be inside the input directory (this includes
+     paths through symbolic links).
   5. Existing output path must be of the same type as the input path.
      In other words, both paths must be either file or directory.
+     Symbolic links in input paths are treated as files. Symbolic links
+     in output paths are followed, unless they are dangling.
   6. Empty paths are not allowed.
-  7. Breaking any of these rules will result in error.
+  7. Each output path may be used only once (this includes different paths
+     to the same file), unless all its input paths are directories which
+     are merged into it. This rule is not checked with `--stream` flag.
+  8. Breaking any of these rules will result in error.
 
 Interpretation:
 
-  1. After reading `>` instruction, an operation is performed between
-     the last known input/output path.
-  2. The default operation is to move the file or directory.
+  1. The whole input is read and validated before any operation is performed.
+     Each `>` instruction then results in an operation between the last
+     known input/output path.
+     With the `--stream` flag, operations are performed while the input
+     is being read, so an invalid line is only detected after all
+     previous operations were already performed.
+  2. Operations are reordered so that each existing input path is read
+     before another operation overwrites it (e.g. `1 -> 2`, `2 -> 3`).
+     Cycles such as swaps (`a -> b`, `b -> a`) are resolved using a
+     temporary path. Operations are not reordered with `--stream` flag.
+  3. The default operation is to move the file or directory.
      If both paths are on the same device, this will result in rename.
      If both paths are on different devices, the item will be copied
      on the output device and then deleted from the input device.
-  3. The copy operation can be enabled using the `-c, --copy` flag,
+     Each file is first copied to a temporary path next to the output path,
+     synced to disk, checked to have the same type and size and renamed
+     to the output path. Only then the input file is deleted, so an
+     interrupted move never loses data. Content of the copy is compared
+     with the input file only with the `--verify` option.
+     Other rename errors are reported without trying to copy the item.
+     The `--no-copy-fallback` flag reports an error instead of copying
+     items between different devices.
+  4. The copy operation can be enabled using the `-c, --copy` flag,
      Directories are copied recursively with their content.
-  4. If the destination directory is non-empty, the source directory will
+     Files hard linked to each other inside a copied directory remain
+     hard linked in the copy.
+     File content is cloned using copy-on-write reflinks (e.g. on Btrfs
+     or XFS) when possible. This can be changed using the `--reflink` option.
+     Holes of sparse files are kept. This can be changed using the `--sparse`
+     option. Both options also apply to moves between different devices.
+     Copied files and directories keep their permissions by default.
+     This can be changed using the `--preserve` option with a comma separated
+     list of attributes (e.g. `--preserve=mode,timestamps`).
+     Moves between different devices preserve all attributes by default,
+     so that the result does not differ from a rename. Failure to preserve
+     ownership, extended attributes or ACLs is ignored in such case.
+     Failures of explicitly listed attributes can be ignored using
+     the `--ignore-preserve-errors` flag.
+     Similarly, the `-l, --link` flag creates hard links to input files.
+     Directories are recreated at the output path and their files are linked.
+     Both paths must be on the same file system.
+     The `-s, --symlink` flag creates a symbolic link to the input path.
+     Link target is absolute, unless the `-r, --relative` flag is used.
+     On Windows, creating symbolic links requires Developer Mode
+     or administrator privileges.
+     Directories are linked as a whole, unless the `-e, --expand-dirs` flag
+     is used. Then they are recreated like with `-l, --link` flag.
+     Symbolic links in input paths (including links inside directories)
+     are copied or linked as links by default. The `-L, --dereference` flag
+     follows them and copies or links their targets instead.
+     Moves always transfer the links themselves.
+     Files copied to a symbolic link are written to its target, other
+     operations replace the link itself. Directories transferred
+     to a symbolic link to a directory are merged with its target.
+     Special files (named pipes, sockets and devices) are recreated instead
+     of copying their content. The `--special` option limits which types
+     are recreated (e.g. `--special=fifo,socket`), copying other types fails.
+  5. If the destination directory is non-empty, the source directory will
      be merged with it. This means that only the files that exists in both
      directories will be overwritten. This rule is applied recursively
      for subdirectories.
-  5. Any non-existent directories in the output path are automatically created.
+  6. Existing output files are overwritten by default. This can be changed
+     using the `--overwrite` option, which is applied to each file,
+     including files inside merged directories. Any mode other than
+     `always` never replaces an output file, even if it was created
+     by another process in the meantime.
+  7. Any non-existent directories in the output path are automatically created.
+  8. The `-n, --dry-run` flag validates all instructions and prints
+     the operations without performing them on the file system.
+  9. The `--atomic` flag reverts all performed operations in reverse order
+     when any operation fails. Moved items are moved back, copies and
+     created directories are removed. Files replaced by `--overwrite=always`
+     are moved aside to `.<NAME>.sren-replaced<N>` and restored, they are
+     removed only after all operations succeed.
+ 10. The `-k, --keep-going` flag continues with other operations when
+     an operation fails. Each failure is reported together with its line
+     number and the number of failed and all operations (one per `>`
+     instruction) is reported at the end, even if none failed.
+     Invalid instructions are skipped before any operation is performed.
+     Operations depending on a failed one are skipped as well, so that
+     an input path is never overwritten before it was transferred
+     (e.g. `1 -> 2` after `2 -> 3` failed).
+ 11. The `--failed <FILE>` option writes input/output paths of each failed
+     operation (including invalid instructions) to the file (e.g. `/dev/fd/3`).
+     The output uses the input format and the `-0, --null` delimiter,
+     so it can be used as input of another run with the same flags.
+     The option requires the `-k, --keep-going` flag, so that no instruction
+     is left out of the output.
+ 12. The `--sync` flag syncs content of copied files and directories
+     containing changed paths (including the input directory of renames)
+     to disk before the operation is reported as done and the next one
+     begins, so performed operations survive a power loss.
+ 13. The `--verify[=<ALGORITHM>]` option compares checksums of each copied
+     file and its copy, which is read back from the disk. This applies
+     to copies and moves between different devices. An operation fails
+     when the checksums differ and the input file of a move is kept.
+
+Journal:
+
+  1. The `--journal <FILE>` option records each performed change
+     of the file system to the file, including changes of operations
+     which failed halfway. The file must not exist, so a journal
+     of a previous run is never overwritten.
+  2. The journal uses the input format: `<` is followed by a source path,
+     `>` by the path where it was moved to, `=` by the path where
+     it was copied to, `&` and `@` by the path where it was hard/symbolically
+     linked to. `+` and `-` are followed by a created/removed directory.
+     Paths are absolute and use the `-0, --null` delimiter.
+  3. Files backed up using `--overwrite=backup` are recorded as moves,
+     so they are restored by undo. Files replaced by `--overwrite=always`
+     are kept as `.<NAME>.sren-replaced<N>` and also recorded as moves.
+  4. The `--undo <FILE>` option reverts all changes recorded in the journal
+     in reverse order. Undo stops on the first error and never overwrites
+     an existing file. Standard input is not read in this case.
+
+Exit status:
+
+  0  All operations were performed.
+  1  Invalid input or an operation failed.
+  2  Invalid command line arguments.
+  3  Some operations failed with the `-k, --keep-going` flag.
 ";
 
 /// Move/copy files using instructions from standard input.
@@ -61,7 +183,114 @@ pub struct Cli {
     #[clap(short, long)]
     pub copy: bool,
 
+    /// Create hard links instead of moving files.
+    #[clap(short, long, conflicts_with = "copy")]
+    pub link: bool,
+
+    /// Create symbolic links instead of moving files.
+    #[clap(short, long, conflicts_with_all = ["copy", "link"])]
+    pub symlink: bool,
+
+    /// Create symbolic links relative to the output directory.
+    #[clap(short, long, requires = "symlink")]
+    pub relative: bool,
+
+    /// Create symbolic links to files inside input directories, not to directories themselves.
+    #[clap(short, long, requires = "symlink")]
+    pub expand_dirs: bool,
+
+    /// Fail instead of copying and deleting items moved between different devices.
+    #[clap(long, conflicts_with_all = ["copy", "link", "symlink"])]
+    pub no_copy_fallback: bool,
+
+    /// Follow symbolic links in input paths when copying or linking.
+    #[clap(short = 'L', long, overrides_with = "no_dereference")]
+    pub dereference: bool,
+
+    /// Copy or link symbolic links themselves, not their targets (default).
+    #[clap(short = 'P', long, overrides_with = "dereference")]
+    pub no_dereference: bool,
+
+    /// What to do when an output file already exists.
+    #[clap(long, value_enum, value_name = "MODE", default_value_t)]
+    pub overwrite: OverwriteMode,
+
+    /// Whether to clone content of copied files (copy-on-write).
+    #[clap(
+        long,
+        value_enum,
+        value_name = "MODE",
+        default_value_t,
+        num_args = 0..=1,
+        default_missing_value = "always"
+    )]
+    pub reflink: ReflinkMode,
+
+    /// Whether to keep holes in copied sparse files.
+    #[clap(long, value_enum, value_name = "MODE", default_value_t)]
+    pub sparse: SparseMode,
+
+    /// Preserve attributes of copied files and directories.
+    #[clap(long, value_enum, value_name = "ATTRS", value_delimiter = ',')]
+    pub preserve: Option<Vec<Attribute>>,
+
+    /// Ignore failures to preserve attributes (e.g. unsupported by file system).
+    #[clap(long)]
+    pub ignore_preserve_errors: bool,
+
+    /// Special files recreated by copies (all by default, none if empty).
+    #[clap(long, value_enum, value_name = "TYPES", value_delimiter = ',', num_args = 0..)]
+    pub special: Option<Vec<SpecialType>>,
+
+    /// Sync each performed operation to disk before the next one.
+    #[clap(long)]
+    pub sync: bool,
+
+    /// Verify content of copied files using checksums.
+    #[clap(
+        long,
+        value_enum,
+        value_name = "ALGORITHM",
+        num_args = 0..=1,
+        default_missing_value = "blake3",
+        conflicts_with_all = ["link", "symlink"]
+    )]
+    pub verify: Option<Checksum>,
+
     /// Enable verbose output.
     #[clap(short, long)]
     pub verbose: bool,
+
+    /// Validate and print operations without performing them.
+    #[clap(short = 'n', long)]
+    pub dry_run: bool,
+
+    /// Process instructions as they are read, without validating the whole input first.
+    #[clap(long)]
+    pub stream: bool,
+
+    /// Revert all performed operations when any operation fails.
+    #[clap(long, visible_alias = "transaction")]
+    pub atomic: bool,
+
+    /// Continue with other operations when an operation fails.
+    #[clap(short, long, conflicts_with = "atomic")]
+    pub keep_going: bool,
+
+    /// Write instructions of failed operations to a file.
+    #[clap(
+        long,
+        value_name = "FILE",
+        requires = "keep_going",
+        conflicts_with = "undo"
+    )]
+    pub failed: Option<PathBuf>,
+
+    /// Record performed operations to a new journal file.
+    #[clap(long, value_name = "FILE", conflicts_with_all = ["dry_run", "atomic"])]
+    pub journal: Option<PathBuf>,
+
+    /// Revert operations recorded in a journal file.
+    #[clap(long, value_name = "FILE", conflicts_with_all = ["dry_run", "journal", "stream", "atomic"])]
+    pub undo: Option<PathBuf>,
 }
@@ -0,0 +1,378 @@
+use crate::fs::revert;
+use crate::fs::transfer;
+use crate::fs::Action;
+use crate::fs::TransferMode;
+use crate::fs::TransferOptions;
+use crate::journal::JournalWriter;
+use crate::path::PathWriter;
+use crate::sim::Simulator;
+use crate::verbose::Logger;
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::BufWriter;
+use std::path::Path;
+use termcolor::WriteColor;
+
+#[derive(Debug, thiserror::Error)]
+pub enum RollbackError {
+    #[error("{cause}\nRollback succeeded, {count} performed changes were reverted")]
+    Succeeded { cause: Box<dyn Error>, count: usize },
+    #[error("{cause}\nRollback failed, {failed} of {count} performed changes could not be reverted\n{first}")]
+    Failed {
+        cause: Box<dyn Error>,
+        first: Box<dyn Error>,
+        failed: usize,
+        count: usize,
+    },
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Failed instruction on line #{line}\n{cause}")]
+pub struct FailedInstruction {
+    cause: Box<dyn Error>,
+    line: usize,
+}
+
+// Counts failed operations when continuing after a failure.
+// Steps of the same instruction (e.g. a swap using a temporary path) count as one operation.
+#[derive(Debug, Default, thiserror::Error)]
+#[error("{} of {} operations failed", .failed.len(), .lines.len())]
+pub struct Failures {
+    lines: BTreeSet<usize>,
+    failed: BTreeSet<usize>,
+}
+
+impl Failures {
+    // Returns the first failure of each instruction, so the caller can report it.
+    pub fn record(
+        &mut self,
+        result: Result<(), Box<dyn Error>>,
+        line: usize,
+    ) -> Option<FailedInstruction> {
+        self.lines.insert(line);
+
+        match result {
+            Err(cause) if self.failed.insert(line) => Some(FailedInstruction { cause, line }),
+            _ => None,
+        }
+    }
+
+    // Returns number of succeeded operations, failures are reported as an error.
+    pub fn into_result(self) -> Result<usize, Self> {
+        if self.failed.is_empty() {
+            Ok(self.lines.len())
+        } else {
+            Err(self)
+        }
+    }
+}
+
+pub struct Executor<W> {
+    options: TransferOptions,
+    logger: Option<Logger<W>>,
+    simulator: Option<Simulator>,
+    journal: Option<JournalWriter<BufWriter<File>>>,
+    failed: Option<PathWriter<BufWriter<File>>>,
+    transaction: Option<Vec<Action>>,
+}
+
+impl<W> Executor<W> {
+    pub fn new(
+        options: TransferOptions,
+        logger: Option<Logger<W>>,
+        simulator: Option<Simulator>,
+        journal: Option<JournalWriter<BufWriter<File>>>,
+        failed: Option<PathWriter<BufWriter<File>>>,
+        transaction: bool,
+    ) -> Self {
+        Self {
+            options,
+            logger,
+            simulator,
+            journal,
+            failed,
+            transaction: if transaction { Some(Vec::new()) } else { None },
+        }
+    }
+}
+
+impl<W: WriteColor> Executor<W> {
+    pub fn execute(
+        &mut self,
+        src: &Path,
+        dst: &Path,
+        mode: TransferMode,
+    ) -> Result<(), Box<dyn Error>> {
+        if let Some(logger) = &mut self.logger {
+            logger.begin(src, dst, mode)?;
+        }
+
+        let mut actions = Vec::new();
+        let result = match &mut self.simulator {
+            Some(simulator) => simulator.transfer(src, dst, mode, &self.options),
+            None => transfer(src, dst, mode, &self.options, &mut actions),
+        };
+
+        // Record also actions of a failed transfer, they were performed anyway.
+        if let Some(journal) = &mut self.journal {
+            for action in &actions {
+                journal.write(action)?;
+            }
+            journal.flush()?;
+        }
+
+        if let Some(transaction) = &mut self.transaction {
+            transaction.append(&mut actions);
+        }
+
+        if result.is_err() {
+            self.write_failed(src, dst)?;
+        }
+
+        self.finish(result.map_err(Into::into))
+    }
+
+    // Also used for instructions rejected before any operation is performed.
+    pub fn write_failed(&mut self, src: &Path, dst: &Path) -> io::Result<()> {
+        if let Some(failed) = &mut self.failed {
+            failed.write_pair(src, dst)?;
+            failed.flush()?;
+        }
+        Ok(())
+    }
+
+    pub fn revert(&mut self, action: &Action) -> Result<(), Box<dyn Error>> {
+        if let Some(logger) = &mut self.logger {
+            logger.begin_revert(action)?;
+        }
+
+        let result = revert(action);
+        self.finish(result.map_err(Into::into))
+    }
+
+    // Removes files replaced within transaction, they are no longer needed for rollback.
+    pub fn commit(&mut self) -> Result<(), Box<dyn Error>> {
+        for action in self.transaction.take().unwrap_or_default() {
+            if let Action::Replace(_, path) = action {
+                fs::remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Reverts all actions performed within transaction in reverse order.
+    // Continues after a failure, so that as many actions as possible are reverted.
+    pub fn rollback(&mut self, cause: Box<dyn Error>) -> Box<dyn Error> {
+        let actions = match self.transaction.take() {
+            Some(actions) if !actions.is_empty() => actions,
+            _ => return cause,
+        };
+
+        let mut first = None;
+        let mut failed = 0;
+
+        for action in actions.iter().rev() {
+            if let Err(error) = self.revert(action) {
+                first.get_or_insert(error);
+                failed += 1;
+            }
+        }
+
+        let count = actions.len();
+
+        match first {
+            None => RollbackError::Succeeded { cause, count }.into(),
+            Some(first) => RollbackError::Failed {
+                cause,
+                first,
+                failed,
+                count,
+            }
+            .into(),
+        }
+    }
+
+    fn finish(&mut self, result: Result<(), Box<dyn Error>>) -> Result<(), Box<dyn Error>> {
+        if let Some(logger) = &mut self.logger {
+            match result {
+                Ok(()) => logger.success()?,
+                Err(_) => logger.failure()?,
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::line::Separator;
+    use assert_fs::prelude::*;
+    use assert_fs::TempDir;
+    use claim::assert_err;
+    use claim::assert_ok;
+    use claim::assert_ok_eq;
+    use termcolor::NoColor;
+
+    #[test]
+    fn rollback() {
+        let root_dir = assert_ok!(TempDir::new());
+        let src_file = root_dir.child("a");
+        let dst_file = root_dir.child("b/c");
+        assert_ok!(src_file.write_str("1"));
+
+        let mut executor = executor(true);
+        assert_ok!(executor.execute(src_file.path(), dst_file.path(), TransferMode::Move));
+        let cause = assert_err!(executor.execute(
+            &root_dir.join("d"),
+            &root_dir.join("e"),
+            TransferMode::Move
+        ));
+        let error = executor.rollback(cause);
+
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "Path '{}' does not exist or you don't have access\n\
+                 Rollback succeeded, 2 performed changes were reverted",
+                root_dir.join("d").to_string_lossy()
+            )
+        );
+
+        src_file.assert("1");
+        root_dir.child("b").assert(predicates::path::missing());
+    }
+
+    #[test]
+    fn rollback_replaced() {
+        let root_dir = assert_ok!(TempDir::new());
+        let src_file = root_dir.child("a");
+        let dst_file = root_dir.child("b");
+        assert_ok!(src_file.write_str("1"));
+        assert_ok!(dst_file.write_str("2"));
+
+        let mut executor = executor(true);
+        assert_ok!(executor.execute(src_file.path(), dst_file.path(), TransferMode::Move));
+        executor.rollback("Failure".into());
+
+        src_file.assert("1");
+        dst_file.assert("2");
+        root_dir
+            .child(".b.sren-replaced0")
+            .assert(predicates::path::missing());
+    }
+
+    #[test]
+    fn commit_replaced() {
+        let root_dir = assert_ok!(TempDir::new());
+        let src_file = root_dir.child("a");
+        let dst_file = root_dir.child("b");
+        assert_ok!(src_file.write_str("1"));
+        assert_ok!(dst_file.write_str("2"));
+
+        let mut executor = executor(true);
+        assert_ok!(executor.execute(src_file.path(), dst_file.path(), TransferMode::Move));
+        root_dir.child(".b.sren-replaced0").assert("2");
+        assert_ok!(executor.commit());
+
+        dst_file.assert("1");
+        root_dir
+            .child(".b.sren-replaced0")
+            .assert(predicates::path::missing());
+    }
+
+    #[test]
+    fn commit_moved_to_replaced_path() {
+        let root_dir = assert_ok!(TempDir::new());
+        let src_file = root_dir.child("b");
+        let dst_file = root_dir.child(".b.sren-replaced0");
+        assert_ok!(src_file.write_str("1"));
+
+        let mut executor = executor(true);
+        assert_ok!(executor.execute(src_file.path(), dst_file.path(), TransferMode::Move));
+        assert_ok!(executor.commit());
+
+        dst_file.assert("1");
+    }
+
+    #[test]
+    fn rollback_without_transaction() {
+        let root_dir = assert_ok!(TempDir::new());
+        let src_file = root_dir.child("a");
+        let dst_file = root_dir.child("b");
+        assert_ok!(src_file.write_str("1"));
+
+        let mut executor = executor(false);
+        assert_ok!(executor.execute(src_file.path(), dst_file.path(), TransferMode::Move));
+        let error = executor.rollback("Failure".into());
+
+        assert_eq!(error.to_string(), "Failure");
+        dst_file.assert("1");
+    }
+
+    #[test]
+    fn failures() {
+        let mut failures = Failures::default();
+        assert!(failures.record(Ok(()), 2).is_none());
+        let failed = failures.record(Err("Failure".into()), 4);
+        assert_eq!(
+            failed.map(|failed| failed.to_string()).as_deref(),
+            Some("Failed instruction on line #4\nFailure")
+        );
+        assert!(failures.record(Ok(()), 6).is_none());
+        assert!(failures.record(Err("Failure".into()), 4).is_none());
+        assert!(failures.record(Ok(()), 2).is_none());
+
+        let error = assert_err!(failures.into_result());
+        assert_eq!(error.to_string(), "1 of 3 operations failed");
+
+        let mut failures = Failures::default();
+        failures.record(Ok(()), 2);
+        failures.record(Ok(()), 4);
+        assert_ok_eq!(failures.into_result(), 2);
+    }
+
+    #[test]
+    fn failed_instructions() {
+        let root_dir = assert_ok!(TempDir::new());
+        let src_file = root_dir.child("a");
+        let failed_file = root_dir.child("failed");
+        assert_ok!(src_file.write_str("1"));
+
+        let writer = BufWriter::new(assert_ok!(File::create(failed_file.path())));
+        let failed = PathWriter::new(writer, Separator::Null);
+        let mut executor = Executor::<NoColor<Vec<u8>>>::new(
+            TransferOptions::default(),
+            None,
+            None,
+            None,
+            Some(failed),
+            false,
+        );
+
+        let dst_file = root_dir.child("b");
+        assert_ok!(executor.execute(src_file.path(), dst_file.path(), TransferMode::Copy));
+        assert_err!(executor.execute(Path::new("c"), Path::new("d"), TransferMode::Copy));
+
+        assert_ok!(executor.write_failed(Path::new("e"), Path::new("f")));
+
+        failed_file.assert("<c\0>d\0<e\0>f\0");
+    }
+
+    fn executor(transaction: bool) -> Executor<NoColor<Vec<u8>>> {
+        Executor::new(
+            TransferOptions {
+                keep_replaced: transaction,
+                ..TransferOptions::default()
+            },
+            None,
+            None,
+            None,
+            None,
+            transaction,
+        )
+    }
+}
@@ -1,59 +1,424 @@
-use fs_extra::dir;
-use fs_extra::error::Error;
-use fs_extra::error::ErrorKind;
-use fs_extra::error::Result;
-use fs_extra::file;
+use crate::checksum;
+use crate::checksum::Checksum;
+use crate::sys;
+use clap::ValueEnum;
 use once_cell::sync::Lazy;
 use same_file::is_same_file;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::env;
+use std::ffi::OsString;
+use std::fmt::Display;
 use std::fs;
+use std::fs::File;
+use std::fs::FileTimes;
+use std::fs::Metadata;
+use std::fs::OpenOptions;
+use std::io;
+use std::path::Component;
 use std::path::Path;
+use std::path::PathBuf;
+
+#[derive(PartialEq, Debug)]
+pub enum ErrorKind {
+    NotFound,
+    AlreadyExists,
+    Other,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("{message}")]
+pub struct Error {
+    pub kind: ErrorKind,
+    message: String,
+}
+
+impl Error {
+    pub fn new(kind: ErrorKind, message: &str) -> Self {
+        Self {
+            kind,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        let kind = match error.kind() {
+            io::ErrorKind::NotFound => ErrorKind::NotFound,
+            io::ErrorKind::AlreadyExists => ErrorKind::AlreadyExists,
+            _ => ErrorKind::Other,
+        };
+        Self::new(kind, &error.to_string())
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
 
 #[derive(PartialEq, Debug)]
 pub enum FileType {
     File,
     Dir,
+    Symlink,
+    Special(SpecialType),
     Unknown,
 }
 
-impl From<&Path> for FileType {
-    fn from(path: &Path) -> Self {
-        match path.metadata() {
-            Ok(metadata) => {
-                if metadata.is_dir() {
-                    FileType::Dir
-                } else {
-                    FileType::File
-                }
-            }
+impl FileType {
+    // Type of the symbolic link target, or the path itself if it is not a link.
+    pub fn dereferenced(path: &Path) -> Self {
+        Self::from_metadata(path.metadata())
+    }
+
+    // Type of the symbolic link target, or the link itself if it is dangling.
+    pub fn resolved(path: &Path) -> Self {
+        match Self::from(path) {
+            FileType::Symlink => match Self::dereferenced(path) {
+                FileType::Unknown => FileType::Symlink,
+                file_type => file_type,
+            },
+            file_type => file_type,
+        }
+    }
+
+    fn from_metadata(metadata: io::Result<Metadata>) -> Self {
+        match metadata {
+            Ok(metadata) if metadata.is_dir() => FileType::Dir,
+            Ok(metadata) if metadata.is_symlink() => FileType::Symlink,
+            Ok(metadata) => match special_type(&metadata) {
+                Some(special_type) => FileType::Special(special_type),
+                None => FileType::File,
+            },
             Err(_) => FileType::Unknown,
         }
     }
 }
 
-#[derive(Clone, Copy)]
+fn special_type(metadata: &Metadata) -> Option<SpecialType> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileTypeExt;
+        let file_type = metadata.file_type();
+
+        if file_type.is_fifo() {
+            Some(SpecialType::Fifo)
+        } else if file_type.is_socket() {
+            Some(SpecialType::Socket)
+        } else if file_type.is_block_device() {
+            Some(SpecialType::Block)
+        } else if file_type.is_char_device() {
+            Some(SpecialType::Char)
+        } else {
+            None
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        None
+    }
+}
+
+// Symbolic links are not followed, so dangling links are also recognized.
+impl From<&Path> for FileType {
+    fn from(path: &Path) -> Self {
+        Self::from_metadata(path.symlink_metadata())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub enum TransferMode {
     Move,
     Copy,
+    Link,
+    Symlink,
 }
 
-static FILE_COPY_OPTIONS: Lazy<file::CopyOptions> = Lazy::new(|| {
-    let mut options = file::CopyOptions::new();
-    options.overwrite = true;
-    options.skip_exist = false;
-    options
-});
+#[derive(Clone, Copy, PartialEq, Debug, Default, ValueEnum)]
+pub enum OverwriteMode {
+    /// Fail when an output file already exists.
+    Never,
+    /// Skip input files whose output file already exists.
+    Skip,
+    /// Overwrite existing output files.
+    #[default]
+    Always,
+    /// Rename existing output files to `name~` before overwriting them.
+    Backup,
+    /// Use the first free `name (N).ext` path instead of existing output files.
+    Rename,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, ValueEnum)]
+pub enum Attribute {
+    /// Permissions.
+    Mode,
+    /// Access and modification times.
+    Timestamps,
+    /// User and group.
+    Ownership,
+    /// Extended attributes (including SELinux context).
+    Xattr,
+    /// POSIX access control lists.
+    Acl,
+}
+
+impl Attribute {
+    fn name(&self) -> &'static str {
+        match self {
+            Attribute::Mode => "mode",
+            Attribute::Timestamps => "timestamps",
+            Attribute::Ownership => "ownership",
+            Attribute::Xattr => "extended attributes",
+            Attribute::Acl => "ACL",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, ValueEnum)]
+pub enum SpecialType {
+    /// Named pipes.
+    Fifo,
+    /// Unix domain sockets.
+    Socket,
+    /// Block devices.
+    Block,
+    /// Character devices.
+    Char,
+}
+
+impl SpecialType {
+    fn name(&self) -> &'static str {
+        match self {
+            SpecialType::Fifo => "named pipe",
+            SpecialType::Socket => "socket",
+            SpecialType::Block => "block device",
+            SpecialType::Char => "character device",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Default, ValueEnum)]
+pub enum SparseMode {
+    /// Keep holes of sparse input files.
+    #[default]
+    Auto,
+    /// Keep holes and also create holes for blocks of zeros.
+    Always,
+    /// Write holes as zeros.
+    Never,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Default, ValueEnum)]
+pub enum ReflinkMode {
+    /// Clone file content when possible, copy it otherwise.
+    #[default]
+    Auto,
+    /// Clone file content or fail.
+    Always,
+    /// Always copy file content.
+    Never,
+}
+
+#[derive(Clone, Default)]
+pub struct TransferOptions {
+    pub overwrite: OverwriteMode,
+    pub reflink: ReflinkMode,
+    pub sparse: SparseMode,
+    pub preserve: Option<Vec<Attribute>>,
+    pub ignore_preserve_errors: bool,
+    pub special: Option<Vec<SpecialType>>,
+    pub relative: bool,
+    pub expand_dirs: bool,
+    pub dereference: bool,
+    pub no_copy_fallback: bool,
+    pub sync: bool,
+    pub verify: Option<Checksum>,
+    pub keep_replaced: bool,
+}
+
+impl TransferOptions {
+    // Moves preserve all attributes by default, so they are indistinguishable
+    // from renames. Other modes preserve only permissions by default.
+    fn preserves(&self, attribute: Attribute, mode: TransferMode) -> bool {
+        match &self.preserve {
+            Some(attributes) => attributes.contains(&attribute),
+            None => mode == TransferMode::Move || attribute == Attribute::Mode,
+        }
+    }
+
+    // Copies recreate all special files by default.
+    fn recreates(&self, special_type: SpecialType) -> bool {
+        match &self.special {
+            Some(special_types) => special_types.contains(&special_type),
+            None => true,
+        }
+    }
+
+    // Moves always transfer symbolic links themselves.
+    pub fn dereferences(&self, mode: TransferMode) -> bool {
+        self.dereference && mode != TransferMode::Move
+    }
+
+    fn metadata(&self, path: &Path, mode: TransferMode) -> io::Result<Metadata> {
+        if self.dereferences(mode) {
+            path.metadata()
+        } else {
+            path.symlink_metadata()
+        }
+    }
+
+    pub fn source_type(&self, src: &Path, mode: TransferMode) -> FileType {
+        FileType::from_metadata(self.metadata(src, mode))
+    }
+
+    pub fn is_same_file(&self, src: &Path, dst: &Path, mode: TransferMode) -> io::Result<bool> {
+        let src_metadata = self.metadata(src, mode)?;
+        let dst_metadata = self.metadata(dst, mode)?;
+
+        match (sys::file_id(&src_metadata), sys::file_id(&dst_metadata)) {
+            (Some(src_id), Some(dst_id)) => Ok(src_id == dst_id),
+            _ => is_same_file(src, dst),
+        }
+    }
+}
+
+// Change of the file system performed during transfer.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Action {
+    Move(PathBuf, PathBuf),
+    // Output file moved aside before it was replaced, so it can be restored.
+    Replace(PathBuf, PathBuf),
+    Copy(PathBuf, PathBuf),
+    Link(PathBuf, PathBuf),
+    Symlink(PathBuf, PathBuf),
+    CreateDir(PathBuf),
+    RemoveDir(PathBuf),
+}
 
-static DIR_COPY_OPTIONS: Lazy<dir::CopyOptions> = Lazy::new(|| {
-    let mut options = dir::CopyOptions::new();
-    options.overwrite = true;
-    options.skip_exist = false;
-    options.copy_inside = true;
-    options.content_only = true;
-    options
+static REVERT_OPTIONS: Lazy<TransferOptions> = Lazy::new(|| TransferOptions {
+    overwrite: OverwriteMode::Never,
+    ..TransferOptions::default()
 });
 
-pub fn transfer(src: &Path, dst: &Path, mode: TransferMode) -> Result<()> {
-    match (FileType::from(src), FileType::from(dst)) {
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut result = PathBuf::new();
+
+    if path.is_relative() {
+        if let Ok(current_dir) = env::current_dir() {
+            result.push(current_dir);
+        }
+    }
+
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => match result.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    result.pop();
+                }
+                Some(Component::RootDir | Component::Prefix(_)) => {}
+                _ => result.push(component),
+            },
+            _ => result.push(component),
+        }
+    }
+
+    result
+}
+
+// Resolves symbolic links in the existing part of the path.
+pub fn canonicalize_existing(path: &Path) -> PathBuf {
+    let path = normalize(path);
+
+    for ancestor in path.ancestors() {
+        if let Ok(ancestor_path) = ancestor.canonicalize() {
+            return match path.strip_prefix(ancestor) {
+                Ok(rest) if !rest.as_os_str().is_empty() => ancestor_path.join(rest),
+                _ => ancestor_path,
+            };
+        }
+    }
+
+    path
+}
+
+pub fn temp_path(path: &Path) -> PathBuf {
+    let mut index = 0;
+
+    loop {
+        let mut name = OsString::from(".");
+        name.push(path.file_name().unwrap_or_default());
+        name.push(format!(".sren{}", index));
+
+        let temp_path = path.with_file_name(name);
+        if FileType::from(temp_path.as_path()) == FileType::Unknown {
+            return temp_path;
+        }
+
+        index += 1;
+    }
+}
+
+pub fn backup_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push("~");
+    path.with_file_name(name)
+}
+
+// Replaced files are moved aside, so they can be restored later.
+pub fn replaced_path(path: &Path) -> PathBuf {
+    let mut index = 0;
+
+    loop {
+        let replaced_path = path.with_file_name(replaced_name(path, index));
+        if FileType::from(replaced_path.as_path()) == FileType::Unknown {
+            return replaced_path;
+        }
+
+        index += 1;
+    }
+}
+
+fn replaced_name(path: &Path, index: impl Display) -> OsString {
+    let mut name = OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(format!(".sren-replaced{}", index));
+    name
+}
+
+pub fn numbered_path(path: &Path, exists: impl Fn(&Path) -> bool) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default();
+    let extension = path.extension();
+    let mut index = 1;
+
+    loop {
+        let mut name = stem.to_os_string();
+        name.push(format!(" ({})", index));
+
+        if let Some(extension) = extension {
+            name.push(".");
+            name.push(extension);
+        }
+
+        let numbered_path = path.with_file_name(name);
+        if !exists(&numbered_path) {
+            return numbered_path;
+        }
+
+        index += 1;
+    }
+}
+
+pub fn exists_error(path: &Path) -> Error {
+    Error::new(
+        ErrorKind::AlreadyExists,
+        &format!("Path '{}' already exists", path.to_string_lossy()),
+    )
+}
+
+pub fn check(src: &Path, src_type: &FileType, dst: &Path, dst_type: &FileType) -> Result<()> {
+    match (src_type, dst_type) {
         (FileType::Unknown, _) => Err(Error::new(
             ErrorKind::NotFound,
             &format!(
@@ -62,376 +427,2136 @@ pub fn transfer(src: &Path, dst: &Path, mode: TransferMode) -> Result<()> {
             ),
         )),
 
-        (FileType::File, FileType::Dir) => Err(Error::new(
-            ErrorKind::Other,
-            &format!(
-                "Cannot to overwrite directory '{}' with file '{}'",
-                dst.to_string_lossy(),
-                src.to_string_lossy()
-            ),
-        )),
+        (FileType::File | FileType::Symlink | FileType::Special(_), FileType::Dir) => {
+            Err(Error::new(
+                ErrorKind::Other,
+                &format!(
+                    "Cannot to overwrite directory '{}' with file '{}'",
+                    dst.to_string_lossy(),
+                    src.to_string_lossy()
+                ),
+            ))
+        }
+
+        (FileType::Dir, FileType::File | FileType::Symlink | FileType::Special(_)) => {
+            Err(Error::new(
+                ErrorKind::Other,
+                &format!(
+                    "Cannot to overwrite file '{}' with directory '{}'",
+                    dst.to_string_lossy(),
+                    src.to_string_lossy()
+                ),
+            ))
+        }
+
+        _ => Ok(()),
+    }
+}
+
+// Copying content of special files would block (named pipes) or read
+// whole devices, so they can only be recreated. Moves between different
+// devices always recreate them, so they are indistinguishable from renames.
+pub fn check_special(
+    src: &Path,
+    src_type: &FileType,
+    mode: TransferMode,
+    options: &TransferOptions,
+) -> Result<()> {
+    match src_type {
+        FileType::Special(special_type)
+            if mode == TransferMode::Copy && !options.recreates(*special_type) =>
+        {
+            Err(Error::new(
+                ErrorKind::Other,
+                &format!(
+                    "Cannot copy {} '{}', recreating it is disabled",
+                    special_type.name(),
+                    src.to_string_lossy()
+                ),
+            ))
+        }
+        _ => Ok(()),
+    }
+}
+
+// Output paths of copied files with multiple hard links, by device and inode number.
+type CopiedLinks = HashMap<(u64, u64), PathBuf>;
+
+// Directory transferred into its own subdirectory would be recursively
+// transferred into the newly created output directory again and again.
+pub fn check_nested(
+    src: &Path,
+    src_type: &FileType,
+    dst: &Path,
+    mode: TransferMode,
+    options: &TransferOptions,
+) -> Result<()> {
+    if *src_type != FileType::Dir || (mode == TransferMode::Symlink && !options.expand_dirs) {
+        return Ok(());
+    }
+
+    let src_path = canonicalize_existing(src);
+    let dst_path = canonicalize_existing(dst);
 
-        (FileType::Dir, FileType::File) => Err(Error::new(
+    if dst_path != src_path && dst_path.starts_with(&src_path) {
+        let verb = match mode {
+            TransferMode::Move => "move",
+            TransferMode::Copy => "copy",
+            TransferMode::Link | TransferMode::Symlink => "link",
+        };
+        Err(Error::new(
             ErrorKind::Other,
             &format!(
-                "Cannot to overwrite file '{}' with directory '{}'",
-                dst.to_string_lossy(),
-                src.to_string_lossy()
+                "Cannot {} directory '{}' into its own subdirectory '{}'",
+                verb,
+                src.to_string_lossy(),
+                dst.to_string_lossy()
             ),
-        )),
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+pub fn transfer(
+    src: &Path,
+    dst: &Path,
+    mode: TransferMode,
+    options: &TransferOptions,
+    actions: &mut Vec<Action>,
+) -> Result<()> {
+    check_nested(src, &options.source_type(src, mode), dst, mode, options)?;
+
+    let performed = actions.len();
+    transfer_tree(src, dst, mode, options, actions, &mut CopiedLinks::new())?;
+
+    // Content of copied files is synced when they are written.
+    if options.sync {
+        sync_parent_dirs(actions[performed..].iter().flat_map(|action| match action {
+            Action::Move(src, dst) | Action::Replace(src, dst) => {
+                vec![src.as_path(), dst.as_path()]
+            }
+            Action::Copy(_, dst) | Action::Link(_, dst) | Action::Symlink(_, dst) => {
+                vec![dst.as_path()]
+            }
+            Action::CreateDir(path) | Action::RemoveDir(path) => vec![path.as_path()],
+        }))?;
+    }
+    Ok(())
+}
+
+fn transfer_tree(
+    src: &Path,
+    dst: &Path,
+    mode: TransferMode,
+    options: &TransferOptions,
+    actions: &mut Vec<Action>,
+    copied_links: &mut CopiedLinks,
+) -> Result<()> {
+    let src_type = options.source_type(src, mode);
+    // Output paths are resolved through symbolic links,
+    // so directories are merged into the link target.
+    let dst_type = FileType::resolved(dst);
+
+    check(src, &src_type, dst, &dst_type)?;
+    check_special(src, &src_type, mode, options)?;
+
+    let dst_target;
+    let dst = if is_written_through(&src_type, dst, &dst_type, mode) {
+        dst_target = fs::canonicalize(dst)?;
+        dst_target.as_path()
+    } else {
+        dst
+    };
+
+    if dst_type != FileType::Unknown && options.is_same_file(src, dst, mode)? {
+        // Renaming path to itself may still change case of its name.
+        if mode == TransferMode::Move {
+            fs::rename(src, dst)?;
+            actions.push(Action::Move(src.to_path_buf(), dst.to_path_buf()));
+        }
+        return Ok(());
+    }
+
+    match src_type {
+        FileType::File | FileType::Symlink | FileType::Special(_) => {
+            let dst = match dst_type {
+                FileType::Unknown => dst.to_path_buf(),
+                _ => match options.overwrite {
+                    OverwriteMode::Never => return Err(exists_error(dst)),
+                    OverwriteMode::Skip => return Ok(()),
+                    OverwriteMode::Always if options.keep_replaced => {
+                        let replaced = replaced_path(dst);
+                        fs::rename(dst, &replaced)?;
+                        actions.push(Action::Replace(dst.to_path_buf(), replaced));
+                        dst.to_path_buf()
+                    }
+                    OverwriteMode::Always => dst.to_path_buf(),
+                    OverwriteMode::Backup => {
+                        let mut backup_options = options.clone();
+                        backup_options.overwrite = OverwriteMode::Always;
+                        transfer(
+                            dst,
+                            &backup_path(dst),
+                            TransferMode::Move,
+                            &backup_options,
+                            actions,
+                        )?;
+                        dst.to_path_buf()
+                    }
+                    OverwriteMode::Rename => {
+                        numbered_path(dst, |path| FileType::from(path) != FileType::Unknown)
+                    }
+                },
+            };
 
-        (FileType::File, dst_type) => {
             if let Some(dst_parent) = dst.parent() {
-                dir::create_all(dst_parent, false)?;
+                create_dir_all(dst_parent, actions)?;
             }
-            match mode {
+
+            // Moves record their actions themselves, because a move between
+            // different devices may fail after the output file was created.
+            let result = match mode {
                 TransferMode::Move => {
-                    if fs::rename(src, dst).is_err() {
-                        file::move_file(src, dst, &FILE_COPY_OPTIONS)?;
-                    }
+                    move_file(src, &dst, &src_type, options, actions, copied_links)
                 }
-                TransferMode::Copy => {
-                    if dst_type == FileType::Unknown || !is_same_file(src, dst)? {
-                        file::copy(src, dst, &FILE_COPY_OPTIONS)?;
-                    }
+                TransferMode::Copy => copy_entry(src, &dst, &src_type, mode, options, copied_links)
+                    .map(|()| actions.push(Action::Copy(src.to_path_buf(), dst.clone()))),
+                TransferMode::Link => link_file(src, &dst, options)
+                    .map(|()| actions.push(Action::Link(src.to_path_buf(), dst.clone()))),
+                TransferMode::Symlink => symlink_file(src, &dst, options)
+                    .map(|()| actions.push(Action::Symlink(src.to_path_buf(), dst.clone()))),
+            };
+
+            match result {
+                // Output file was created after we checked it.
+                Err(error)
+                    if is_exists_error(&error) && options.overwrite == OverwriteMode::Skip =>
+                {
+                    Ok(())
                 }
+                result => result,
             }
-            Ok(())
         }
 
-        (FileType::Dir, dst_type) => {
-            dir::create_all(dst, false)?;
+        FileType::Dir => {
+            // Read before directory listing changes its access time.
+            let src_metadata = src.metadata()?;
 
-            match mode {
-                TransferMode::Move => {
-                    if fs::rename(src, dst).is_err() {
-                        dir::move_dir(src, dst, &DIR_COPY_OPTIONS)?;
-                    }
+            if dst_type == FileType::Unknown {
+                if let Some(dst_parent) = dst.parent() {
+                    create_dir_all(dst_parent, actions)?;
                 }
-                TransferMode::Copy => {
-                    if dst_type == FileType::Unknown || !is_same_file(src, dst)? {
-                        dir::copy(src, dst, &DIR_COPY_OPTIONS)?;
+                if mode == TransferMode::Move {
+                    // Directories on different devices are merged into a new one.
+                    match rename(src, dst, options) {
+                        Ok(()) => {
+                            actions.push(Action::Move(src.to_path_buf(), dst.to_path_buf()));
+                            return Ok(());
+                        }
+                        Err(error) if can_copy_across(&error, options) => {}
+                        Err(error) => return Err(move_error(error, src, dst)),
                     }
                 }
+                if mode == TransferMode::Symlink && !options.expand_dirs {
+                    symlink_file(src, dst, options)?;
+                    actions.push(Action::Symlink(src.to_path_buf(), dst.to_path_buf()));
+                    return Ok(());
+                }
+                create_dir_all(dst, actions)?;
+            }
+
+            // Merge directory content, so the overwrite mode is applied to each file.
+            for name in read_dir_names(src)? {
+                let src = src.join(&name);
+                let dst = dst.join(&name);
+                transfer_tree(&src, &dst, mode, options, actions, copied_links)?;
+            }
+
+            // Adding content changes modification time, so attributes are set afterwards.
+            if dst_type == FileType::Unknown {
+                let src_dir = sys::open_dir(src, false)?;
+                let dst_dir = sys::open_dir(dst, true)?;
+                preserve_attributes(&src_dir, &src_metadata, &dst_dir, dst, mode, options)?;
+            }
+
+            // Skipped files remain in the source directory.
+            if mode == TransferMode::Move && read_dir_names(src)?.is_empty() {
+                fs::remove_dir(src)?;
+                actions.push(Action::RemoveDir(src.to_path_buf()));
             }
             Ok(())
         }
+
+        FileType::Unknown => unreachable!("Expected source path to be checked"),
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use assert_fs::prelude::*;
-    use assert_fs::NamedTempFile;
-    use assert_fs::TempDir;
-    use claim::assert_err;
-    use claim::assert_ok;
-    use ntest::*;
+// Copied files are written through symbolic links to existing files, like with `cp`.
+// Other operations replace the link itself.
+pub fn is_written_through(
+    src_type: &FileType,
+    dst: &Path,
+    dst_type: &FileType,
+    mode: TransferMode,
+) -> bool {
+    mode == TransferMode::Copy
+        && *src_type == FileType::File
+        && *dst_type == FileType::File
+        && FileType::from(dst) == FileType::Symlink
+}
 
-    #[test]
-    fn file_type() {
-        let dir = temp_dir();
-        let file = touch(temp_file("a"));
-        let unknown = temp_file("b");
+pub fn revert(action: &Action) -> Result<()> {
+    match action {
+        Action::Move(src, dst) | Action::Replace(src, dst) => transfer(
+            dst,
+            src,
+            TransferMode::Move,
+            &REVERT_OPTIONS,
+            &mut Vec::new(),
+        )?,
+        Action::Copy(_, dst) | Action::Link(_, dst) | Action::Symlink(_, dst) => {
+            fs::remove_file(dst)?
+        }
+        Action::CreateDir(path) => fs::remove_dir(path)?,
+        Action::RemoveDir(path) => fs::create_dir(path)?,
+    }
+    Ok(())
+}
 
-        assert_eq!(FileType::from(dir.path()), FileType::Dir);
-        assert_eq!(FileType::from(file.path()), FileType::File);
-        assert_eq!(FileType::from(unknown.path()), FileType::Unknown);
+fn create_dir_all(path: &Path, actions: &mut Vec<Action>) -> Result<()> {
+    let missing_dirs = path
+        .ancestors()
+        .take_while(|dir| !dir.as_os_str().is_empty() && FileType::from(*dir) == FileType::Unknown)
+        .collect::<Vec<_>>();
+
+    for dir in missing_dirs.into_iter().rev() {
+        match fs::create_dir(dir) {
+            Ok(()) => actions.push(Action::CreateDir(dir.to_path_buf())),
+            Err(error) if error.kind() == io::ErrorKind::AlreadyExists => {}
+            Err(error) => return Err(error.into()),
+        }
+    }
+
+    Ok(())
+}
+
+fn rename(src: &Path, dst: &Path, options: &TransferOptions) -> io::Result<()> {
+    if options.overwrite == OverwriteMode::Always {
+        fs::rename(src, dst)
+    } else {
+        sys::rename_noreplace(src, dst)
+    }
+}
+
+fn move_file(
+    src: &Path,
+    dst: &Path,
+    src_type: &FileType,
+    options: &TransferOptions,
+    actions: &mut Vec<Action>,
+    copied_links: &mut CopiedLinks,
+) -> Result<()> {
+    match rename(src, dst, options) {
+        Ok(()) => {
+            actions.push(Action::Move(src.to_path_buf(), dst.to_path_buf()));
+            Ok(())
+        }
+        Err(error) if can_copy_across(&error, options) => {
+            move_across(src, dst, src_type, options, actions, copied_links)
+        }
+        Err(error) => Err(move_error(error, src, dst)),
+    }
+}
+
+// Only moves between different devices are performed by copying,
+// other rename errors would just make the copy fail or be needlessly slow.
+fn can_copy_across(error: &io::Error, options: &TransferOptions) -> bool {
+    sys::is_cross_device(error) && !options.no_copy_fallback
+}
+
+fn move_error(error: io::Error, src: &Path, dst: &Path) -> Error {
+    if error.kind() == io::ErrorKind::AlreadyExists {
+        exists_error(dst)
+    } else if sys::is_cross_device(&error) {
+        Error::new(
+            ErrorKind::Other,
+            &format!(
+                "Cannot move '{}' to '{}' on a different file system without copying",
+                src.to_string_lossy(),
+                dst.to_string_lossy()
+            ),
+        )
+    } else {
+        Error::new(
+            ErrorKind::Other,
+            &format!(
+                "Cannot move '{}' to '{}'\n{}",
+                src.to_string_lossy(),
+                dst.to_string_lossy(),
+                error
+            ),
+        )
+    }
+}
+
+// File is copied to a temporary path next to the output path, so the output path
+// never contains a partial copy. The copy is synced to disk and its type and size
+// are checked before it replaces the output path. Input file is removed only after that.
+fn move_across(
+    src: &Path,
+    dst: &Path,
+    src_type: &FileType,
+    options: &TransferOptions,
+    actions: &mut Vec<Action>,
+    copied_links: &mut CopiedLinks,
+) -> Result<()> {
+    let temp = temp_path(dst);
+
+    let result = copy_entry(
+        src,
+        &temp,
+        src_type,
+        TransferMode::Move,
+        options,
+        copied_links,
+    )
+    .and_then(|()| {
+        if *src_type == FileType::File {
+            File::open(&temp)?.sync_all()?;
+        }
+        verify_copy(src, &temp, src_type)
+    })
+    .and_then(|()| rename(&temp, dst, options).map_err(|error| link_error(error, dst)));
+
+    if let Err(error) = result {
+        fs::remove_file(&temp).ok();
+        return Err(error);
+    }
+
+    // Other hard links to the file are linked to its final path.
+    for copy in copied_links.values_mut() {
+        if *copy == temp {
+            *copy = dst.to_path_buf();
+        }
+    }
+
+    // Output file is recorded as a copy until the input file is removed,
+    // so that it is reverted even if the removal fails.
+    actions.push(Action::Copy(src.to_path_buf(), dst.to_path_buf()));
+
+    sync_parent_dirs([dst])?;
+    fs::remove_file(src)?;
+
+    actions.pop();
+    actions.push(Action::Move(src.to_path_buf(), dst.to_path_buf()));
+    Ok(())
+}
+
+// Parent directories removed by the transfer are skipped,
+// their removal is synced through the nearest existing ancestor.
+fn sync_parent_dirs<'a>(paths: impl IntoIterator<Item = &'a Path>) -> io::Result<()> {
+    let dirs = paths
+        .into_iter()
+        .map(|path| {
+            path.ancestors()
+                .skip(1)
+                .find(|dir| dir.as_os_str().is_empty() || dir.is_dir())
+                .unwrap_or(path)
+        })
+        .collect::<BTreeSet<_>>();
+
+    for dir in dirs {
+        sys::sync_dir(dir)?;
+    }
+    Ok(())
+}
+
+// Content is compared only when copying with checksum verification.
+fn verify_copy(src: &Path, copy: &Path, src_type: &FileType) -> Result<()> {
+    let same_type = FileType::from(copy) == *src_type;
+    let same_size = *src_type != FileType::File || src.metadata()?.len() == copy.metadata()?.len();
+
+    if same_type && same_size {
+        Ok(())
+    } else {
+        Err(Error::new(
+            ErrorKind::Other,
+            &format!(
+                "Copy '{}' of '{}' differs from the original",
+                copy.to_string_lossy(),
+                src.to_string_lossy()
+            ),
+        ))
+    }
+}
+
+fn copy_entry(
+    src: &Path,
+    dst: &Path,
+    src_type: &FileType,
+    mode: TransferMode,
+    options: &TransferOptions,
+    copied_links: &mut CopiedLinks,
+) -> Result<()> {
+    let metadata = options.metadata(src, mode)?;
+    let id = sys::file_id(&metadata);
+
+    // Other hard links to an already copied file are linked to its copy.
+    // Moved files have their link count decreased, so it is not checked here.
+    if let Some(copy) = id.and_then(|id| copied_links.get(&id)) {
+        if create_link(dst, options, |link| fs::hard_link(copy, link)).is_ok() {
+            return Ok(());
+        }
+    }
+
+    match src_type {
+        FileType::Symlink => copy_symlink(src, dst, options),
+        FileType::Special(_) => copy_special(src, dst, mode, options),
+        _ => copy_file(src, dst, mode, options),
+    }?;
+
+    if let Some(id) = id.filter(|_| sys::link_count(&metadata) > 1) {
+        copied_links.insert(id, dst.to_path_buf());
+    }
+    Ok(())
+}
+
+// Only ownership and permissions are preserved, because special files are not opened.
+fn copy_special(
+    src: &Path,
+    dst: &Path,
+    mode: TransferMode,
+    options: &TransferOptions,
+) -> Result<()> {
+    let metadata = src.metadata()?;
+
+    create_link(dst, options, |node| sys::make_node(node, &metadata))
+        .map_err(|error| link_error(error, dst))?;
+
+    preserve_attribute(Attribute::Ownership, dst, mode, options, &|| {
+        sys::set_node_owner(dst, &metadata)
+    })?;
+    preserve_attribute(Attribute::Mode, dst, mode, options, &|| {
+        fs::set_permissions(dst, metadata.permissions())
+    })
+}
+
+// Link is recreated with the same target, which is not resolved.
+fn copy_symlink(src: &Path, dst: &Path, options: &TransferOptions) -> Result<()> {
+    let target = fs::read_link(src)?;
+    create_link(dst, options, |link| sys::copy_symlink(src, &target, link))
+        .map_err(|error| link_error(error, dst))
+}
+
+fn copy_file(src: &Path, dst: &Path, mode: TransferMode, options: &TransferOptions) -> Result<()> {
+    let mut reader = File::open(src)?;
+    let metadata = reader.metadata()?;
+
+    // Existing output file is replaced only after the copy is complete,
+    // so its content is kept when copying fails. Cloning directly into it
+    // would also keep its tail, because cloning never shrinks a file.
+    let replace =
+        options.overwrite == OverwriteMode::Always && FileType::from(dst) != FileType::Unknown;
+    let path = if replace {
+        temp_path(dst)
+    } else {
+        dst.to_path_buf()
+    };
+
+    // Exclusive creation fails when the output file already exists.
+    let mut writer = match OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(&path)
+    {
+        Ok(writer) => writer,
+        Err(error) if error.kind() == io::ErrorKind::AlreadyExists => {
+            return Err(exists_error(dst));
+        }
+        Err(error) => return Err(error.into()),
+    };
+
+    let result = copy_content(&mut reader, &mut writer, src, dst, options)
+        .and_then(|hash| match options.verify {
+            Some(checksum) => verify_content(&mut reader, &mut writer, hash, checksum, src, dst),
+            None => Ok(()),
+        })
+        .and_then(|()| preserve_attributes(&reader, &metadata, &writer, dst, mode, options))
+        .and_then(|()| {
+            if options.sync {
+                writer.sync_all()?;
+            }
+            if replace {
+                fs::rename(&path, dst)?;
+            }
+            Ok(())
+        });
+
+    if result.is_err() {
+        fs::remove_file(&path).ok();
+    }
+    result
+}
+
+fn preserve_attributes(
+    src: &File,
+    metadata: &Metadata,
+    dst: &File,
+    dst_path: &Path,
+    mode: TransferMode,
+    options: &TransferOptions,
+) -> Result<()> {
+    let preserve = |attribute: Attribute, apply: &dyn Fn() -> io::Result<()>| {
+        preserve_attribute(attribute, dst_path, mode, options, apply)
+    };
+
+    // Changing owner may clear setuid/setgid bits, so it goes first.
+    preserve(Attribute::Ownership, &|| sys::set_owner(dst, metadata))?;
+    preserve(Attribute::Xattr, &|| sys::copy_xattrs(src, dst, false))?;
+    preserve(Attribute::Mode, &|| {
+        dst.set_permissions(metadata.permissions())
+    })?;
+    // Setting mode would change ACL mask, so ACL goes after it.
+    preserve(Attribute::Acl, &|| sys::copy_xattrs(src, dst, true))?;
+    preserve(Attribute::Timestamps, &|| {
+        let times = FileTimes::new()
+            .set_accessed(metadata.accessed()?)
+            .set_modified(metadata.modified()?);
+        dst.set_times(times)
+    })
+}
+
+fn preserve_attribute(
+    attribute: Attribute,
+    dst: &Path,
+    mode: TransferMode,
+    options: &TransferOptions,
+    apply: &dyn Fn() -> io::Result<()>,
+) -> Result<()> {
+    if !options.preserves(attribute, mode) {
+        return Ok(());
     }
+    match apply() {
+        Ok(()) => Ok(()),
+        Err(_) if options.ignore_preserve_errors => Ok(()),
+        // Only privileged user can give away files and not every
+        // file system supports extended attributes.
+        Err(_) if options.preserve.is_none() && attribute != Attribute::Mode => Ok(()),
+        Err(error) => Err(Error::new(
+            ErrorKind::Other,
+            &format!(
+                "Cannot preserve {} of '{}'\n{}",
+                attribute.name(),
+                dst.to_string_lossy(),
+                error
+            ),
+        )),
+    }
+}
+
+// Returns checksum of the content when it was computed during copying.
+fn copy_content(
+    reader: &mut File,
+    writer: &mut File,
+    src: &Path,
+    dst: &Path,
+    options: &TransferOptions,
+) -> Result<Option<Vec<u8>>> {
+    if reflink_file(reader, writer, src, dst, options)? {
+        return Ok(None);
+    }
+
+    let sparse = match options.sparse {
+        SparseMode::Auto => sys::is_sparse(reader)?,
+        SparseMode::Always => true,
+        SparseMode::Never => false,
+    };
+
+    if sparse {
+        sys::copy_sparse(reader, writer, options.sparse == SparseMode::Always)?;
+    } else if let Some(checksum) = options.verify {
+        return Ok(Some(checksum::copy(reader, writer, checksum)?));
+    } else {
+        sys::copy_content(reader, writer)?;
+    }
+    Ok(None)
+}
+
+// Output file is read back from the disk, not from the page cache,
+// so that a corrupted write is detected.
+fn verify_content(
+    reader: &mut File,
+    writer: &mut File,
+    src_hash: Option<Vec<u8>>,
+    checksum: Checksum,
+    src: &Path,
+    dst: &Path,
+) -> Result<()> {
+    let src_hash = match src_hash {
+        Some(src_hash) => src_hash,
+        None => checksum::compute(reader, checksum)?,
+    };
+
+    writer.sync_all()?;
+    sys::drop_cache(writer)?;
+    let dst_hash = checksum::compute(writer, checksum)?;
+
+    if src_hash == dst_hash {
+        Ok(())
+    } else {
+        Err(Error::new(
+            ErrorKind::Other,
+            &format!(
+                "Checksum of '{}' does not match '{}'",
+                dst.to_string_lossy(),
+                src.to_string_lossy()
+            ),
+        ))
+    }
+}
+
+// Returns whether the file content was cloned.
+fn reflink_file(
+    reader: &File,
+    writer: &File,
+    src: &Path,
+    dst: &Path,
+    options: &TransferOptions,
+) -> Result<bool> {
+    if options.reflink == ReflinkMode::Never {
+        return Ok(false);
+    }
+
+    match sys::reflink(reader, writer) {
+        Ok(()) => Ok(true),
+        Err(_) if options.reflink == ReflinkMode::Auto => Ok(false),
+        Err(error) => Err(Error::new(
+            ErrorKind::Other,
+            &format!(
+                "Cannot reflink '{}' to '{}'\n{}",
+                src.to_string_lossy(),
+                dst.to_string_lossy(),
+                error
+            ),
+        )),
+    }
+}
+
+fn link_file(src: &Path, dst: &Path, options: &TransferOptions) -> Result<()> {
+    // Hard link to a symbolic link does not follow it.
+    let target = if options.dereference {
+        fs::canonicalize(src)?
+    } else {
+        src.to_path_buf()
+    };
+
+    match create_link(dst, options, |link| fs::hard_link(&target, link)) {
+        Err(error) if sys::is_cross_device(&error) => Err(Error::new(
+            ErrorKind::Other,
+            &format!(
+                "Cannot link '{}' to '{}' on a different file system",
+                src.to_string_lossy(),
+                dst.to_string_lossy()
+            ),
+        )),
+        result => result.map_err(|error| link_error(error, dst)),
+    }
+}
+
+fn symlink_file(src: &Path, dst: &Path, options: &TransferOptions) -> Result<()> {
+    let target = symlink_target(src, dst, options.relative);
+    create_link(dst, options, |link| sys::symlink(&target, link))
+        .map_err(|error| link_error(error, dst))
+}
+
+fn create_link(
+    dst: &Path,
+    options: &TransferOptions,
+    create: impl Fn(&Path) -> io::Result<()>,
+) -> io::Result<()> {
+    if options.overwrite == OverwriteMode::Always && FileType::from(dst) != FileType::Unknown {
+        // Linking fails when the output file already exists,
+        // so we link to a temporary path and replace the output file with it.
+        let temp = temp_path(dst);
+        create(&temp)?;
+        fs::rename(&temp, dst).inspect_err(|_| {
+            fs::remove_file(&temp).ok();
+        })
+    } else {
+        create(dst)
+    }
+}
+
+fn link_error(error: io::Error, dst: &Path) -> Error {
+    if error.kind() == io::ErrorKind::AlreadyExists {
+        exists_error(dst)
+    } else {
+        error.into()
+    }
+}
+
+// Absolute target is resolved lexically, relative target is computed
+// from the (already created) parent directory of the link.
+fn symlink_target(src: &Path, dst: &Path, relative: bool) -> PathBuf {
+    let target = normalize(src);
+
+    if !relative {
+        return target;
+    }
+
+    let resolve = |path: &Path| match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => match parent.canonicalize() {
+            Ok(parent) => parent.join(name),
+            Err(_) => path.to_path_buf(),
+        },
+        _ => path.to_path_buf(),
+    };
+
+    let target = resolve(&target);
+    let link = resolve(&normalize(dst));
+
+    match link.parent() {
+        Some(base) => relative_path(&target, base),
+        None => target,
+    }
+}
+
+pub fn relative_path(path: &Path, base: &Path) -> PathBuf {
+    let path_components = path.components().collect::<Vec<_>>();
+    let base_components = base.components().collect::<Vec<_>>();
+
+    let common = path_components
+        .iter()
+        .zip(&base_components)
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let mut result = PathBuf::new();
+
+    for _ in common..base_components.len() {
+        result.push(Component::ParentDir);
+    }
+    for component in &path_components[common..] {
+        result.push(component);
+    }
+    if result.as_os_str().is_empty() {
+        result.push(Component::CurDir);
+    }
+
+    result
+}
+
+fn is_exists_error(error: &Error) -> bool {
+    error.kind == ErrorKind::AlreadyExists
+}
+
+fn read_dir_names(path: &Path) -> Result<Vec<OsString>> {
+    let mut names = fs::read_dir(path)?
+        .map(|entry| entry.map(|entry| entry.file_name()))
+        .collect::<io::Result<Vec<_>>>()?;
+    names.sort();
+    Ok(names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::mkdir;
+    use crate::testing::temp_dir;
+    use crate::testing::temp_file;
+    use crate::testing::touch;
+    use crate::testing::write;
+    #[cfg(unix)]
+    use assert_fs::fixture::ChildPath;
+    use assert_fs::prelude::*;
+    use claim::assert_err;
+    use claim::assert_ok;
+    use ntest::*;
+    use std::time::Duration;
+    use std::time::SystemTime;
+    use test_case::test_case;
+
+    #[test]
+    fn file_type() {
+        let dir = temp_dir();
+        let file = touch(temp_file("a"));
+        let unknown = temp_file("b");
+        let symlink = dir.child("c");
+        assert_ok!(symlink.symlink_to_file(file.path()));
+        let dangling = dir.child("d");
+        assert_ok!(dangling.symlink_to_file(unknown.path()));
+
+        assert_eq!(FileType::from(dir.path()), FileType::Dir);
+        assert_eq!(FileType::from(file.path()), FileType::File);
+        assert_eq!(FileType::from(unknown.path()), FileType::Unknown);
+        assert_eq!(FileType::from(symlink.path()), FileType::Symlink);
+        assert_eq!(FileType::from(dangling.path()), FileType::Symlink);
+        assert_eq!(FileType::dereferenced(symlink.path()), FileType::File);
+        assert_eq!(FileType::dereferenced(dangling.path()), FileType::Unknown);
+        assert_eq!(FileType::resolved(symlink.path()), FileType::File);
+        assert_eq!(FileType::resolved(dangling.path()), FileType::Symlink);
+    }
+
+    #[test]
+    fn normalize_path() {
+        let current_dir = assert_ok!(env::current_dir());
+
+        assert_eq!(normalize(Path::new("/a/./b/../c")), Path::new("/a/c"));
+        assert_eq!(normalize(Path::new("/..")), Path::new("/"));
+        assert_eq!(normalize(Path::new("a/b/..")), current_dir.join("a"));
+    }
+
+    #[test]
+    fn temp_path_next_to() {
+        let root_dir = temp_dir();
+        touch(root_dir.child(".a.sren0"));
+
+        assert_eq!(temp_path(&root_dir.join("a")), root_dir.join(".a.sren1"));
+    }
+
+    #[test]
+    fn backup_path_of() {
+        assert_eq!(backup_path(Path::new("a/b.c")), Path::new("a/b.c~"));
+    }
+
+    #[test]
+    fn replaced_path_next_to() {
+        let root_dir = temp_dir();
+        touch(root_dir.child(".a.sren-replaced0"));
+
+        let replaced_path = replaced_path(&root_dir.join("a"));
+        assert_eq!(replaced_path, root_dir.join(".a.sren-replaced1"));
+    }
+
+    #[test]
+    fn numbered_path_of() {
+        let exists = |path: &Path| path == Path::new("a/b (1).c");
+        assert_eq!(
+            numbered_path(Path::new("a/b.c"), exists),
+            Path::new("a/b (2).c")
+        );
+        assert_eq!(
+            numbered_path(Path::new("a/.b"), exists),
+            Path::new("a/.b (1)")
+        );
+    }
+
+    #[test]
+    fn path_not_found() {
+        let src_file = temp_file("a");
+
+        let error = assert_err!(transfer(
+            src_file.path(),
+            Path::new("b"),
+            TransferMode::Move, // Mode is irrelevant
+            &TransferOptions::default(),
+            &mut Vec::new(),
+        ));
+
+        assert_eq!(format!("{:?}", error.kind), "NotFound");
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "Path '{}' does not exist or you don't have access",
+                src_file.path().to_string_lossy()
+            )
+        );
+
+        src_file.assert(predicates::path::missing());
+    }
+
+    #[test]
+    fn overwrite_dir_with_file() {
+        let src_file = touch(temp_file("a"));
+        let dst_dir = temp_dir();
+
+        let error = assert_err!(transfer(
+            src_file.path(),
+            dst_dir.path(),
+            TransferMode::Move, // Mode is irrelevant
+            &TransferOptions::default(),
+            &mut Vec::new(),
+        ));
+
+        assert_eq!(format!("{:?}", error.kind), "Other");
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "Cannot to overwrite directory '{}' with file '{}'",
+                dst_dir.path().to_string_lossy(),
+                src_file.path().to_string_lossy()
+            )
+        );
+
+        src_file.assert(predicates::path::is_file());
+        dst_dir.assert(predicates::path::is_dir());
+    }
+
+    #[test]
+    fn overwrite_file_with_dir() {
+        let src_dir = temp_dir();
+        let dst_file = touch(temp_file("a"));
+
+        let error = assert_err!(transfer(
+            src_dir.path(),
+            dst_file.path(),
+            TransferMode::Move, // Mode is irrelevant
+            &TransferOptions::default(),
+            &mut Vec::new(),
+        ));
+
+        assert_eq!(format!("{:?}", error.kind), "Other");
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "Cannot to overwrite file '{}' with directory '{}'",
+                dst_file.path().to_string_lossy(),
+                src_dir.path().to_string_lossy()
+            )
+        );
+
+        src_dir.assert(predicates::path::is_dir());
+        dst_file.assert(predicates::path::is_file());
+    }
+
+    #[test]
+    fn rename_file() {
+        let src_file = write(temp_file("a"), "1");
+        let dst_file = temp_file("b");
+
+        assert_ok!(transfer(
+            src_file.path(),
+            dst_file.path(),
+            TransferMode::Move,
+            &TransferOptions::default(),
+            &mut Vec::new(),
+        ));
+
+        src_file.assert(predicates::path::missing());
+        dst_file.assert("1");
+    }
+
+    #[test]
+    fn rename_file_to_itself() {
+        let src_file = write(temp_file("a"), "1");
+
+        assert_ok!(transfer(
+            src_file.path(),
+            src_file.path(),
+            TransferMode::Move,
+            &TransferOptions::default(),
+            &mut Vec::new(),
+        ));
+
+        src_file.assert("1");
+    }
+
+    #[test]
+    fn move_file_to_other() {
+        let src_file = write(temp_file("a"), "1");
+        let dst_file = write(temp_file("b"), "2");
+
+        assert_ok!(transfer(
+            src_file.path(),
+            dst_file.path(),
+            TransferMode::Move,
+            &TransferOptions::default(),
+            &mut Vec::new(),
+        ));
+
+        src_file.assert(predicates::path::missing());
+        dst_file.assert("1");
+    }
+
+    #[test]
+    fn copy_file() {
+        let src_file = write(temp_file("a"), "1");
+        let dst_file = temp_file("b");
+
+        assert_ok!(transfer(
+            src_file.path(),
+            dst_file.path(),
+            TransferMode::Copy,
+            &TransferOptions::default(),
+            &mut Vec::new(),
+        ));
+
+        src_file.assert("1");
+        dst_file.assert("1");
+    }
+
+    // This tests that we do not copy a file into itself, which would
+    // either truncate it or never finish reading what we just wrote.
+    // Timeout is to ensure the running test does not hang forever.
+    #[test]
+    #[timeout(5000)]
+    fn copy_file_to_itself() {
+        let src_file = write(temp_file("a"), "1");
+
+        assert_ok!(transfer(
+            src_file.path(),
+            src_file.path(),
+            TransferMode::Copy,
+            &TransferOptions::default(),
+            &mut Vec::new(),
+        ));
+
+        src_file.assert("1");
+    }
+
+    #[test]
+    fn copy_file_to_other() {
+        let src_file = write(temp_file("a"), "1");
+        let dst_file = write(temp_file("b"), "2");
+
+        assert_ok!(transfer(
+            src_file.path(),
+            dst_file.path(),
+            TransferMode::Copy,
+            &TransferOptions::default(),
+            &mut Vec::new(),
+        ));
+
+        src_file.assert("1");
+        dst_file.assert("1");
+    }
+
+    #[test]
+    fn rename_dir() {
+        let root_dir = temp_dir();
+
+        let src_dir = mkdir(root_dir.child("a"));
+        let src_file = write(src_dir.child("c"), "1");
+
+        let dst_dir = root_dir.child("b");
+        let dst_file = dst_dir.child("c");
+
+        assert_ok!(transfer(
+            src_dir.path(),
+            dst_dir.path(),
+            TransferMode::Move,
+            &TransferOptions::default(),
+            &mut Vec::new()
+        ));
+
+        src_dir.assert(predicates::path::missing());
+        src_file.assert(predicates::path::missing());
+
+        dst_dir.assert(predicates::path::is_dir());
+        dst_file.assert("1");
+    }
+
+    #[test]
+    fn rename_dir_to_itself() {
+        let src_dir = temp_dir();
+        let src_file = write(src_dir.child("a"), "1");
+
+        assert_ok!(transfer(
+            src_dir.path(),
+            src_dir.path(),
+            TransferMode::Move,
+            &TransferOptions::default(),
+            &mut Vec::new()
+        ));
+
+        src_dir.assert(predicates::path::is_dir());
+        src_file.assert("1");
+    }
+
+    #[test]
+    fn move_dir_to_other() {
+        let root_dir = temp_dir();
+
+        let src_dir = mkdir(root_dir.child("a"));
+        let src_file = write(src_dir.child("c"), "1");
+
+        let dst_dir = mkdir(root_dir.child("b"));
+        let dst_file = write(dst_dir.child("c"), "2");
+
+        assert_ok!(transfer(
+            src_dir.path(),
+            dst_dir.path(),
+            TransferMode::Move,
+            &TransferOptions::default(),
+            &mut Vec::new()
+        ));
+
+        src_dir.assert(predicates::path::missing());
+        src_file.assert(predicates::path::missing());
+
+        dst_dir.assert(predicates::path::is_dir());
+        dst_file.assert("1");
+    }
+
+    #[test]
+    fn copy_dir() {
+        let root_dir = temp_dir();
+
+        let src_dir = mkdir(root_dir.child("a"));
+        let src_file = write(src_dir.child("c"), "1");
+
+        let dst_dir = root_dir.child("b");
+        let dst_file = dst_dir.child("c");
+
+        assert_ok!(transfer(
+            src_dir.path(),
+            dst_dir.path(),
+            TransferMode::Copy,
+            &TransferOptions::default(),
+            &mut Vec::new()
+        ));
+
+        src_dir.assert(predicates::path::is_dir());
+        src_file.assert("1");
+
+        dst_dir.assert(predicates::path::is_dir());
+        dst_file.assert("1");
+    }
+
+    // This tests that we do not copy a file into itself, which would
+    // either truncate it or never finish reading what we just wrote.
+    // Timeout is to ensure the running test does not hang forever.
+    #[test]
+    #[timeout(5000)]
+    fn copy_dir_to_itself() {
+        let src_dir = temp_dir();
+        let src_file = write(src_dir.child("a"), "1");
+
+        assert_ok!(transfer(
+            src_dir.path(),
+            src_dir.path(),
+            TransferMode::Copy,
+            &TransferOptions::default(),
+            &mut Vec::new()
+        ));
+
+        src_dir.assert(predicates::path::is_dir());
+        src_file.assert("1");
+    }
+
+    #[test]
+    fn copy_dir_to_other() {
+        let root_dir = temp_dir();
+
+        let src_dir = mkdir(root_dir.child("a"));
+        let src_file = write(src_dir.child("c"), "1");
+
+        let dst_dir = mkdir(root_dir.child("b"));
+        let dst_file = write(dst_dir.child("c"), "2");
+
+        assert_ok!(transfer(
+            src_dir.path(),
+            dst_dir.path(),
+            TransferMode::Copy,
+            &TransferOptions::default(),
+            &mut Vec::new()
+        ));
+
+        src_dir.assert(predicates::path::is_dir());
+        src_file.assert("1");
+
+        dst_dir.assert(predicates::path::is_dir());
+        dst_file.assert("1");
+    }
+
+    #[test]
+    fn move_file_overwrite_never() {
+        let src_file = write(temp_file("a"), "1");
+        let dst_file = write(temp_file("b"), "2");
+
+        let error = assert_err!(transfer(
+            src_file.path(),
+            dst_file.path(),
+            TransferMode::Move,
+            &overwrite_options(OverwriteMode::Never),
+            &mut Vec::new()
+        ));
+
+        assert_eq!(format!("{:?}", error.kind), "AlreadyExists");
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "Path '{}' already exists",
+                dst_file.path().to_string_lossy()
+            )
+        );
+
+        src_file.assert("1");
+        dst_file.assert("2");
+    }
+
+    #[test]
+    fn move_file_overwrite_skip() {
+        let src_file = write(temp_file("a"), "1");
+        let dst_file = write(temp_file("b"), "2");
+
+        assert_ok!(transfer(
+            src_file.path(),
+            dst_file.path(),
+            TransferMode::Move,
+            &overwrite_options(OverwriteMode::Skip),
+            &mut Vec::new()
+        ));
+
+        src_file.assert("1");
+        dst_file.assert("2");
+    }
+
+    // Cloning is tested only when TMPDIR is on a file system with reflinks (e.g. Btrfs).
+    #[test]
+    fn copy_file_reflink() {
+        for reflink in [ReflinkMode::Auto, ReflinkMode::Always, ReflinkMode::Never] {
+            let root_dir = temp_dir();
+            let src_file = write(root_dir.child("a"), "1");
+            let dst_file = write(root_dir.child("b"), &"2".repeat(10 * 1024));
+
+            let options = TransferOptions {
+                reflink,
+                ..TransferOptions::default()
+            };
+
+            let result = transfer(
+                src_file.path(),
+                dst_file.path(),
+                TransferMode::Copy,
+                &options,
+                &mut Vec::new(),
+            );
+
+            // Cloning is not supported by all file systems.
+            if reflink == ReflinkMode::Always && result.is_err() {
+                dst_file.assert("2".repeat(10 * 1024));
+                continue;
+            }
+
+            assert_ok!(result);
+            src_file.assert("1");
+            dst_file.assert("1");
+        }
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn copy_file_reflink_always_failure() {
+        let root_dir = temp_dir();
+        let dst_file = write(root_dir.child("a"), "1");
+
+        let options = TransferOptions {
+            reflink: ReflinkMode::Always,
+            ..TransferOptions::default()
+        };
+
+        // Files cannot be cloned between different file systems.
+        let error = assert_err!(transfer(
+            Path::new("/proc/self/stat"),
+            dst_file.path(),
+            TransferMode::Copy,
+            &options,
+            &mut Vec::new()
+        ));
+
+        assert!(error
+            .to_string()
+            .starts_with("Cannot reflink '/proc/self/stat'"));
+        dst_file.assert("1");
+        root_dir
+            .child(".a.sren0")
+            .assert(predicates::path::missing());
+    }
+
+    #[test]
+    fn copy_dir_preserve_timestamps() {
+        let root_dir = temp_dir();
+        let src_dir = mkdir(root_dir.child("a"));
+        let src_file = write(src_dir.child("b"), "1");
+        let dst_dir = root_dir.child("c");
+        let dst_file = dst_dir.child("b");
+
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        let times = FileTimes::new().set_accessed(time).set_modified(time);
+        let src = assert_ok!(File::options().write(true).open(src_file.path()));
+        assert_ok!(src.set_times(times));
+        assert_ok!(assert_ok!(sys::open_dir(src_dir.path(), true)).set_times(times));
+
+        let options = TransferOptions {
+            preserve: Some(vec![Attribute::Timestamps]),
+            ..TransferOptions::default()
+        };
+
+        assert_ok!(transfer(
+            src_dir.path(),
+            dst_dir.path(),
+            TransferMode::Copy,
+            &options,
+            &mut Vec::new()
+        ));
+
+        // Checked before reading content which changes access time.
+        for path in [dst_dir.path(), dst_file.path()] {
+            let metadata = assert_ok!(path.metadata());
+            assert_eq!(assert_ok!(metadata.modified()), time);
+            assert_eq!(assert_ok!(metadata.accessed()), time);
+        }
+
+        dst_file.assert("1");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn copy_file_preserve_xattr() {
+        let root_dir = temp_dir();
+        let src_file = write(root_dir.child("a"), "1");
+        let dst_file = root_dir.child("b");
+
+        let src = assert_ok!(File::open(src_file.path()));
+        if sys::set_xattr(&src, "user.sren", b"1").is_err() {
+            return; // File system without extended attributes
+        }
+
+        let options = TransferOptions {
+            preserve: Some(vec![Attribute::Xattr]),
+            ..TransferOptions::default()
+        };
+
+        assert_ok!(transfer(
+            src_file.path(),
+            dst_file.path(),
+            TransferMode::Copy,
+            &options,
+            &mut Vec::new()
+        ));
+
+        let dst = assert_ok!(File::open(dst_file.path()));
+        assert_eq!(
+            assert_ok!(sys::get_xattr(&dst, "user.sren")),
+            Some(b"1".to_vec())
+        );
+    }
+
+    #[test]
+    fn copy_file_to_symlink() {
+        let root_dir = temp_dir();
+        let src_file = write(root_dir.child("a"), "1");
+        let dst_file = write(root_dir.child("b"), "2");
+        let dst_link = root_dir.child("c");
+        assert_ok!(dst_link.symlink_to_file(dst_file.path()));
+
+        assert_ok!(transfer(
+            src_file.path(),
+            dst_link.path(),
+            TransferMode::Copy,
+            &TransferOptions::default(),
+            &mut Vec::new()
+        ));
+
+        dst_file.assert("1");
+        assert_eq!(FileType::from(dst_link.path()), FileType::Symlink);
+    }
+
+    #[test]
+    fn move_file_to_symlink() {
+        let root_dir = temp_dir();
+        let src_file = write(root_dir.child("a"), "1");
+        let dst_file = write(root_dir.child("b"), "2");
+        let dst_link = root_dir.child("c");
+        assert_ok!(dst_link.symlink_to_file(dst_file.path()));
+
+        assert_ok!(transfer(
+            src_file.path(),
+            dst_link.path(),
+            TransferMode::Move,
+            &TransferOptions::default(),
+            &mut Vec::new()
+        ));
+
+        dst_file.assert("2");
+        dst_link.assert("1");
+        assert_eq!(FileType::from(dst_link.path()), FileType::File);
+    }
+
+    #[test]
+    fn copy_dir_to_symlink() {
+        let root_dir = temp_dir();
+        let src_dir = mkdir(root_dir.child("a"));
+        write(src_dir.child("b"), "1");
+        let dst_dir = mkdir(root_dir.child("c"));
+        let dst_link = root_dir.child("d");
+        assert_ok!(dst_link.symlink_to_dir(dst_dir.path()));
+
+        assert_ok!(transfer(
+            src_dir.path(),
+            dst_link.path(),
+            TransferMode::Copy,
+            &TransferOptions::default(),
+            &mut Vec::new()
+        ));
+
+        dst_dir.child("b").assert("1");
+        assert_eq!(FileType::from(dst_link.path()), FileType::Symlink);
+    }
+
+    #[test]
+    fn copy_file_overwrite_backup() {
+        let root_dir = temp_dir();
+        let src_file = write(root_dir.child("a"), "1");
+        let dst_file = write(root_dir.child("b"), "2");
+        let backup_file = write(root_dir.child("b~"), "3");
+
+        assert_ok!(transfer(
+            src_file.path(),
+            dst_file.path(),
+            TransferMode::Copy,
+            &overwrite_options(OverwriteMode::Backup),
+            &mut Vec::new()
+        ));
+
+        src_file.assert("1");
+        dst_file.assert("1");
+        backup_file.assert("2");
+    }
+
+    #[test]
+    fn copy_file_overwrite_keep_replaced() {
+        let root_dir = temp_dir();
+        let src_file = write(root_dir.child("a"), "1");
+        let dst_file = write(root_dir.child("b"), "2");
+        let replaced_file = root_dir.child(".b.sren-replaced0");
+        let mut actions = Vec::new();
+
+        assert_ok!(transfer(
+            src_file.path(),
+            dst_file.path(),
+            TransferMode::Copy,
+            &TransferOptions {
+                keep_replaced: true,
+                ..TransferOptions::default()
+            },
+            &mut actions
+        ));
+
+        src_file.assert("1");
+        dst_file.assert("1");
+        replaced_file.assert("2");
+        assert_eq!(
+            actions,
+            [
+                Action::Replace(dst_file.to_path_buf(), replaced_file.to_path_buf()),
+                Action::Copy(src_file.to_path_buf(), dst_file.to_path_buf()),
+            ]
+        );
+    }
+
+    #[test]
+    fn move_file_overwrite_rename() {
+        let root_dir = temp_dir();
+        let src_file = write(root_dir.child("a"), "1");
+        let dst_file = write(root_dir.child("b.txt"), "2");
+        let other_file = write(root_dir.child("b (1).txt"), "3");
+        let renamed_file = root_dir.child("b (2).txt");
+
+        assert_ok!(transfer(
+            src_file.path(),
+            dst_file.path(),
+            TransferMode::Move,
+            &overwrite_options(OverwriteMode::Rename),
+            &mut Vec::new()
+        ));
+
+        src_file.assert(predicates::path::missing());
+        dst_file.assert("2");
+        other_file.assert("3");
+        renamed_file.assert("1");
+    }
+
+    #[test]
+    fn move_dir_overwrite_skip() {
+        let root_dir = temp_dir();
+
+        let src_dir = mkdir(root_dir.child("a"));
+        let src_file_1 = write(src_dir.child("c"), "1");
+        let src_file_2 = write(src_dir.child("d/e"), "2");
+
+        let dst_dir = mkdir(root_dir.child("b"));
+        let dst_file_1 = write(dst_dir.child("c"), "3");
+        let dst_file_2 = dst_dir.child("d/e");
+
+        assert_ok!(transfer(
+            src_dir.path(),
+            dst_dir.path(),
+            TransferMode::Move,
+            &overwrite_options(OverwriteMode::Skip),
+            &mut Vec::new()
+        ));
+
+        src_file_1.assert("1");
+        src_file_2.assert(predicates::path::missing());
+        src_dir.child("d").assert(predicates::path::missing());
+
+        dst_file_1.assert("3");
+        dst_file_2.assert("2");
+    }
+
+    #[test]
+    fn copy_dir_overwrite_backup() {
+        let root_dir = temp_dir();
+
+        let src_dir = mkdir(root_dir.child("a"));
+        let src_file = write(src_dir.child("c"), "1");
+
+        let dst_dir = mkdir(root_dir.child("b"));
+        let dst_file = write(dst_dir.child("c"), "2");
+        let backup_file = dst_dir.child("c~");
 
-    #[test]
-    fn copy_options() {
-        // These options have to be the same
-        assert_eq!(DIR_COPY_OPTIONS.overwrite, FILE_COPY_OPTIONS.overwrite);
-        assert_eq!(DIR_COPY_OPTIONS.skip_exist, FILE_COPY_OPTIONS.skip_exist);
-        assert_eq!(DIR_COPY_OPTIONS.buffer_size, FILE_COPY_OPTIONS.buffer_size);
+        assert_ok!(transfer(
+            src_dir.path(),
+            dst_dir.path(),
+            TransferMode::Copy,
+            &overwrite_options(OverwriteMode::Backup),
+            &mut Vec::new()
+        ));
+
+        src_file.assert("1");
+        dst_file.assert("1");
+        backup_file.assert("2");
     }
 
     #[test]
-    fn path_not_found() {
-        let src_file = temp_file("a");
+    fn link_file() {
+        let root_dir = temp_dir();
+        let src_file = write(root_dir.child("a"), "1");
+        let dst_file = write(root_dir.child("b"), "2");
 
-        let error = assert_err!(transfer(
+        assert_ok!(transfer(
             src_file.path(),
-            &Path::new("b"),
-            TransferMode::Move // Mode is irrelevant
+            dst_file.path(),
+            TransferMode::Link,
+            &TransferOptions::default(),
+            &mut Vec::new()
         ));
 
-        assert_eq!(format!("{:?}", error.kind), "NotFound");
-        assert_eq!(
-            error.to_string(),
-            format!(
-                "Path '{}' does not exist or you don't have access",
-                src_file.path().to_string_lossy()
-            )
-        );
-
-        src_file.assert(predicates::path::missing());
+        src_file.assert("1");
+        dst_file.assert("1");
+        assert!(assert_ok!(is_same_file(src_file.path(), dst_file.path())));
     }
 
     #[test]
-    fn overwrite_dir_with_file() {
-        let src_file = touch(temp_file("a"));
-        let dst_dir = temp_dir();
+    fn link_dir() {
+        let root_dir = temp_dir();
+        let src_dir = mkdir(root_dir.child("a"));
+        let src_file = write(src_dir.child("b/c"), "1");
+        let dst_dir = root_dir.child("d");
+        let dst_file = dst_dir.child("b/c");
 
-        let error = assert_err!(transfer(
-            src_file.path(),
+        let mut actions = Vec::new();
+
+        assert_ok!(transfer(
+            src_dir.path(),
             dst_dir.path(),
-            TransferMode::Move // Mode is irrelevant
+            TransferMode::Link,
+            &TransferOptions::default(),
+            &mut actions
         ));
 
-        assert_eq!(format!("{:?}", error.kind), "Other");
+        src_file.assert("1");
+        dst_file.assert("1");
+        assert!(assert_ok!(is_same_file(src_file.path(), dst_file.path())));
         assert_eq!(
-            error.to_string(),
-            format!(
-                "Cannot to overwrite directory '{}' with file '{}'",
-                dst_dir.path().to_string_lossy(),
-                src_file.path().to_string_lossy()
-            )
+            actions,
+            vec![
+                Action::CreateDir(dst_dir.to_path_buf()),
+                Action::CreateDir(dst_dir.join("b")),
+                Action::Link(src_file.to_path_buf(), dst_file.to_path_buf()),
+            ]
         );
-
-        src_file.assert(predicates::path::is_file());
-        dst_dir.assert(predicates::path::is_dir());
     }
 
     #[test]
-    fn overwrite_file_with_dir() {
-        let src_dir = temp_dir();
-        let dst_file = touch(temp_file("a"));
+    fn link_file_overwrite_never() {
+        let root_dir = temp_dir();
+        let src_file = write(root_dir.child("a"), "1");
+        let dst_file = write(root_dir.child("b"), "2");
 
         let error = assert_err!(transfer(
-            src_dir.path(),
+            src_file.path(),
             dst_file.path(),
-            TransferMode::Move // Mode is irrelevant
+            TransferMode::Link,
+            &overwrite_options(OverwriteMode::Never),
+            &mut Vec::new()
         ));
 
-        assert_eq!(format!("{:?}", error.kind), "Other");
-        assert_eq!(
-            error.to_string(),
-            format!(
-                "Cannot to overwrite file '{}' with directory '{}'",
-                dst_file.path().to_string_lossy(),
-                src_dir.path().to_string_lossy()
-            )
-        );
-
-        src_dir.assert(predicates::path::is_dir());
-        dst_file.assert(predicates::path::is_file());
+        assert_eq!(format!("{:?}", error.kind), "AlreadyExists");
+        dst_file.assert("2");
     }
 
     #[test]
-    fn rename_file() {
-        let src_file = write(temp_file("a"), "1");
-        let dst_file = temp_file("b");
+    fn symlink_file() {
+        let root_dir = temp_dir();
+        let src_file = write(root_dir.child("a/b"), "1");
+        let dst_file = root_dir.child("c/d");
 
         assert_ok!(transfer(
             src_file.path(),
             dst_file.path(),
-            TransferMode::Move
+            TransferMode::Symlink,
+            &TransferOptions::default(),
+            &mut Vec::new()
         ));
 
-        src_file.assert(predicates::path::missing());
         dst_file.assert("1");
+        assert_eq!(assert_ok!(fs::read_link(dst_file.path())), src_file.path());
     }
 
-    #[test]
-    fn rename_file_to_itself() {
-        let src_file = write(temp_file("a"), "1");
+    #[test_case(false, "c/d",   "../a"      ; "whole")]
+    #[test_case(true,  "c/d/b", "../../a/b" ; "expanded")]
+    fn symlink_dir(expand_dirs: bool, link: &str, target: &str) {
+        let root_dir = temp_dir();
+        let src_dir = mkdir(root_dir.child("a"));
+        write(src_dir.child("b"), "1");
+        let dst_dir = root_dir.child("c/d");
+
+        let options = TransferOptions {
+            relative: true,
+            expand_dirs,
+            ..TransferOptions::default()
+        };
 
         assert_ok!(transfer(
+            src_dir.path(),
+            dst_dir.path(),
+            TransferMode::Symlink,
+            &options,
+            &mut Vec::new()
+        ));
+
+        dst_dir.child("b").assert("1");
+        assert_eq!(
+            assert_ok!(fs::read_link(root_dir.join(link))),
+            Path::new(target)
+        );
+    }
+
+    #[test]
+    fn move_file_rename_error() {
+        let root_dir = temp_dir();
+        let src_file = write(root_dir.child("a"), "1");
+        let dst_file = root_dir.child("b/c");
+
+        let error = assert_err!(move_file(
             src_file.path(),
-            src_file.path(),
-            TransferMode::Move
+            dst_file.path(),
+            &FileType::File,
+            &TransferOptions::default(),
+            &mut Vec::new(),
+            &mut CopiedLinks::new()
         ));
 
+        let message = format!(
+            "Cannot move '{}' to '{}'\n",
+            src_file.path().to_string_lossy(),
+            dst_file.path().to_string_lossy()
+        );
+        assert!(error.to_string().starts_with(&message));
         src_file.assert("1");
+        root_dir.child("b").assert(predicates::path::missing());
     }
 
     #[test]
-    fn move_file_to_other() {
-        let src_file = write(temp_file("a"), "1");
-        let dst_file = write(temp_file("b"), "2");
+    fn move_file_across() {
+        let root_dir = temp_dir();
+        let src_file = write(root_dir.child("a"), "1");
+        let dst_file = root_dir.child("b");
+        let mut actions = Vec::new();
 
-        assert_ok!(transfer(
+        assert_ok!(move_across(
             src_file.path(),
             dst_file.path(),
-            TransferMode::Move
+            &FileType::File,
+            &TransferOptions::default(),
+            &mut actions,
+            &mut CopiedLinks::new()
         ));
 
         src_file.assert(predicates::path::missing());
         dst_file.assert("1");
+        root_dir
+            .child(".b.sren0")
+            .assert(predicates::path::missing());
+        assert_eq!(
+            actions,
+            [Action::Move(src_file.to_path_buf(), dst_file.to_path_buf())]
+        );
     }
 
     #[test]
-    fn copy_file() {
-        let src_file = write(temp_file("a"), "1");
-        let dst_file = temp_file("b");
+    fn move_file_across_overwrite_never() {
+        let root_dir = temp_dir();
+        let src_file = write(root_dir.child("a"), "1");
+        let dst_file = write(root_dir.child("b"), "2");
+        let mut actions = Vec::new();
 
-        assert_ok!(transfer(
+        let error = assert_err!(move_across(
             src_file.path(),
             dst_file.path(),
-            TransferMode::Copy
+            &FileType::File,
+            &overwrite_options(OverwriteMode::Never),
+            &mut actions,
+            &mut CopiedLinks::new()
         ));
 
+        assert_eq!(format!("{:?}", error.kind), "AlreadyExists");
         src_file.assert("1");
-        dst_file.assert("1");
+        dst_file.assert("2");
+        root_dir
+            .child(".b.sren0")
+            .assert(predicates::path::missing());
+        assert!(actions.is_empty());
     }
 
-    // This tests that we do not call fs_extra::file::copy with the same
-    // source and destination path (in which case fs_extra freezes).
-    // Timeout is to ensure the running test does not hang forever.
     #[test]
-    #[timeout(5000)]
-    fn copy_file_to_itself() {
-        let src_file = write(temp_file("a"), "1");
+    fn copy_file_verify() {
+        let root_dir = temp_dir();
+        let src_file = write(root_dir.child("a"), "1");
+        let dst_file = root_dir.child("b");
+
+        let options = TransferOptions {
+            reflink: ReflinkMode::Never,
+            verify: Some(Checksum::Blake3),
+            ..TransferOptions::default()
+        };
 
         assert_ok!(transfer(
             src_file.path(),
-            src_file.path(),
-            TransferMode::Copy
+            dst_file.path(),
+            TransferMode::Copy,
+            &options,
+            &mut Vec::new()
         ));
 
-        src_file.assert("1");
+        dst_file.assert("1");
     }
 
     #[test]
-    fn copy_file_to_other() {
-        let src_file = write(temp_file("a"), "1");
-        let dst_file = write(temp_file("b"), "2");
-
-        assert_ok!(transfer(
+    fn verify_content_mismatch() {
+        let root_dir = temp_dir();
+        let src_file = write(root_dir.child("a"), "1");
+        let dst_file = root_dir.child("b");
+        let temp_file = write(root_dir.child(".b.sren0"), "2");
+
+        let mut reader = assert_ok!(File::open(src_file.path()));
+        let mut writer = assert_ok!(File::open(temp_file.path()));
+
+        let error = assert_err!(verify_content(
+            &mut reader,
+            &mut writer,
+            None,
+            Checksum::Blake3,
             src_file.path(),
-            dst_file.path(),
-            TransferMode::Copy
+            temp_file.path()
         ));
 
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "Checksum of '{}' does not match '{}'",
+                temp_file.path().to_string_lossy(),
+                src_file.path().to_string_lossy()
+            )
+        );
         src_file.assert("1");
-        dst_file.assert("1");
+        dst_file.assert(predicates::path::missing());
     }
 
     #[test]
-    fn rename_dir() {
+    fn copy_dir_sync() {
         let root_dir = temp_dir();
+        let src_dir = mkdir(root_dir.child("a"));
+        write(src_dir.child("b"), "1");
+        let dst_dir = root_dir.child("c/d");
+
+        let options = TransferOptions {
+            sync: true,
+            ..TransferOptions::default()
+        };
+
+        assert_ok!(transfer(
+            src_dir.path(),
+            dst_dir.path(),
+            TransferMode::Copy,
+            &options,
+            &mut Vec::new()
+        ));
 
+        dst_dir.child("b").assert("1");
+    }
+
+    // Parent directories of moved entries are removed, so they cannot be synced.
+    #[test_case(false ; "rename")]
+    #[test_case(true  ; "merge")]
+    fn move_dir_sync(merge: bool) {
+        let root_dir = temp_dir();
         let src_dir = mkdir(root_dir.child("a"));
-        let src_file = write(src_dir.child("c"), "1");
+        write(src_dir.child("b/c/d"), "1");
+        write(src_dir.child("e/f"), "2");
+        let dst_dir = root_dir.child("g");
 
-        let dst_dir = root_dir.child("b");
-        let dst_file = dst_dir.child("c");
+        if merge {
+            mkdir(dst_dir.child("b"));
+        }
 
-        assert_ok!(transfer(src_dir.path(), dst_dir.path(), TransferMode::Move));
+        let options = TransferOptions {
+            sync: true,
+            ..TransferOptions::default()
+        };
 
-        src_dir.assert(predicates::path::missing());
-        src_file.assert(predicates::path::missing());
+        assert_ok!(transfer(
+            src_dir.path(),
+            dst_dir.path(),
+            TransferMode::Move,
+            &options,
+            &mut Vec::new()
+        ));
 
-        dst_dir.assert(predicates::path::is_dir());
-        dst_file.assert("1");
+        src_dir.assert(predicates::path::missing());
+        dst_dir.child("b/c/d").assert("1");
+        dst_dir.child("e/f").assert("2");
     }
 
     #[test]
-    fn rename_dir_to_itself() {
-        let src_dir = temp_dir();
-        let src_file = write(src_dir.child("a"), "1");
+    fn copy_dir_into_itself() {
+        let root_dir = temp_dir();
+        let src_dir = mkdir(root_dir.child("a"));
+        write(src_dir.child("b"), "1");
+        let src_link = root_dir.child("c");
+        assert_ok!(src_link.symlink_to_dir(src_dir.path()));
+        let dst_dir = src_link.child("d/a");
 
-        assert_ok!(transfer(src_dir.path(), src_dir.path(), TransferMode::Move));
+        let error = assert_err!(transfer(
+            src_dir.path(),
+            dst_dir.path(),
+            TransferMode::Copy,
+            &TransferOptions::default(),
+            &mut Vec::new()
+        ));
 
-        src_dir.assert(predicates::path::is_dir());
-        src_file.assert("1");
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "Cannot copy directory '{}' into its own subdirectory '{}'",
+                src_dir.path().to_string_lossy(),
+                dst_dir.path().to_string_lossy()
+            )
+        );
+        src_dir.child("d").assert(predicates::path::missing());
     }
 
     #[test]
-    fn move_dir_to_other() {
+    fn copy_dir_hard_links() {
         let root_dir = temp_dir();
-
         let src_dir = mkdir(root_dir.child("a"));
-        let src_file = write(src_dir.child("c"), "1");
-
-        let dst_dir = mkdir(root_dir.child("b"));
-        let dst_file = write(dst_dir.child("c"), "2");
+        let src_file = write(src_dir.child("b"), "1");
+        assert_ok!(fs::hard_link(src_file.path(), src_dir.child("c").path()));
+        let dst_dir = root_dir.child("d");
 
-        assert_ok!(transfer(src_dir.path(), dst_dir.path(), TransferMode::Move));
-
-        src_dir.assert(predicates::path::missing());
-        src_file.assert(predicates::path::missing());
+        assert_ok!(transfer(
+            src_dir.path(),
+            dst_dir.path(),
+            TransferMode::Copy,
+            &TransferOptions::default(),
+            &mut Vec::new()
+        ));
 
-        dst_dir.assert(predicates::path::is_dir());
-        dst_file.assert("1");
+        let dst_file = dst_dir.child("b");
+        let dst_link = dst_dir.child("c");
+        dst_link.assert("1");
+        assert!(assert_ok!(is_same_file(dst_file.path(), dst_link.path())));
+        assert!(!assert_ok!(is_same_file(src_file.path(), dst_file.path())));
     }
 
     #[test]
-    fn copy_dir() {
+    fn move_dangling_symlink() {
         let root_dir = temp_dir();
+        let src_link = root_dir.child("a");
+        assert_ok!(src_link.symlink_to_file("x"));
+        let dst_link = root_dir.child("b/c");
 
+        assert_ok!(transfer(
+            src_link.path(),
+            dst_link.path(),
+            TransferMode::Move,
+            &TransferOptions::default(),
+            &mut Vec::new()
+        ));
+
+        src_link.assert(predicates::path::missing());
+        assert_eq!(assert_ok!(fs::read_link(dst_link.path())), Path::new("x"));
+    }
+
+    #[test_case(false ; "preserved")]
+    #[test_case(true  ; "dereferenced")]
+    fn copy_dir_symlinks(dereference: bool) {
+        let root_dir = temp_dir();
         let src_dir = mkdir(root_dir.child("a"));
-        let src_file = write(src_dir.child("c"), "1");
+        write(src_dir.child("b"), "1");
+        assert_ok!(src_dir.child("c").symlink_to_file("b"));
+        if !dereference {
+            // Dangling link cannot be dereferenced.
+            assert_ok!(src_dir.child("d").symlink_to_file("x"));
+        }
+        let dst_dir = root_dir.child("e");
 
-        let dst_dir = root_dir.child("b");
-        let dst_file = dst_dir.child("c");
+        let options = TransferOptions {
+            dereference,
+            ..TransferOptions::default()
+        };
 
-        assert_ok!(transfer(src_dir.path(), dst_dir.path(), TransferMode::Copy));
+        assert_ok!(transfer(
+            src_dir.path(),
+            dst_dir.path(),
+            TransferMode::Copy,
+            &options,
+            &mut Vec::new()
+        ));
 
-        src_dir.assert(predicates::path::is_dir());
-        src_file.assert("1");
+        dst_dir.child("c").assert("1");
+        if dereference {
+            assert_eq!(FileType::from(dst_dir.child("c").path()), FileType::File);
+        } else {
+            assert_eq!(
+                assert_ok!(fs::read_link(dst_dir.child("c"))),
+                Path::new("b")
+            );
+            assert_eq!(
+                assert_ok!(fs::read_link(dst_dir.child("d"))),
+                Path::new("x")
+            );
+        }
+    }
 
-        dst_dir.assert(predicates::path::is_dir());
-        dst_file.assert("1");
+    #[test]
+    fn relative_path_to() {
+        assert_eq!(
+            relative_path(Path::new("/a/b"), Path::new("/a")),
+            Path::new("b")
+        );
+        assert_eq!(
+            relative_path(Path::new("/a/b"), Path::new("/a/c/d")),
+            Path::new("../../b")
+        );
+        assert_eq!(
+            relative_path(Path::new("/a"), Path::new("/a")),
+            Path::new(".")
+        );
     }
 
-    // This tests that we do not call fs_extra::file::copy with the same
-    // source and destination path (in which case fs_extra freezes).
-    // Timeout is to ensure the running test does not hang forever.
     #[test]
+    #[cfg(unix)]
     #[timeout(5000)]
-    fn copy_dir_to_itself() {
-        let src_dir = temp_dir();
-        let src_file = write(src_dir.child("a"), "1");
+    fn copy_fifo() {
+        let root_dir = temp_dir();
+        let src_fifo = mkfifo(root_dir.child("a"));
+        let dst_fifo = root_dir.child("b");
 
-        assert_ok!(transfer(src_dir.path(), src_dir.path(), TransferMode::Copy));
+        assert_ok!(transfer(
+            src_fifo.path(),
+            dst_fifo.path(),
+            TransferMode::Copy,
+            &TransferOptions::default(),
+            &mut Vec::new()
+        ));
 
-        src_dir.assert(predicates::path::is_dir());
-        src_file.assert("1");
+        let dst_type = FileType::from(dst_fifo.path());
+        assert_eq!(dst_type, FileType::Special(SpecialType::Fifo));
     }
 
     #[test]
-    fn copy_dir_to_other() {
+    #[cfg(unix)]
+    fn copy_fifo_disabled() {
+        let root_dir = temp_dir();
+        let src_fifo = mkfifo(root_dir.child("a"));
+        let dst_fifo = root_dir.child("b");
+
+        let options = TransferOptions {
+            special: Some(vec![SpecialType::Block, SpecialType::Char]),
+            ..TransferOptions::default()
+        };
+
+        let error = assert_err!(transfer(
+            src_fifo.path(),
+            dst_fifo.path(),
+            TransferMode::Copy,
+            &options,
+            &mut Vec::new()
+        ));
+
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "Cannot copy named pipe '{}', recreating it is disabled",
+                src_fifo.path().to_string_lossy()
+            )
+        );
+        dst_fifo.assert(predicates::path::missing());
+    }
+
+    #[test]
+    fn revert_actions() {
         let root_dir = temp_dir();
 
         let src_dir = mkdir(root_dir.child("a"));
         let src_file = write(src_dir.child("c"), "1");
+        let other_file = write(root_dir.child("d"), "2");
 
         let dst_dir = mkdir(root_dir.child("b"));
-        let dst_file = write(dst_dir.child("c"), "2");
+        let dst_file = write(dst_dir.child("c"), "3");
+        let backup_file = dst_dir.child("c~");
+        let copy_file = root_dir.child("e/f/g");
 
-        assert_ok!(transfer(src_dir.path(), dst_dir.path(), TransferMode::Copy));
+        let mut actions = Vec::new();
 
-        src_dir.assert(predicates::path::is_dir());
-        src_file.assert("1");
-
-        dst_dir.assert(predicates::path::is_dir());
-        dst_file.assert("1");
-    }
+        assert_ok!(transfer(
+            src_dir.path(),
+            dst_dir.path(),
+            TransferMode::Move,
+            &overwrite_options(OverwriteMode::Backup),
+            &mut actions
+        ));
+        assert_ok!(transfer(
+            other_file.path(),
+            copy_file.path(),
+            TransferMode::Copy,
+            &TransferOptions::default(),
+            &mut actions
+        ));
 
-    fn temp_dir() -> TempDir {
-        assert_ok!(TempDir::new())
-    }
+        src_dir.assert(predicates::path::missing());
+        backup_file.assert("3");
+        copy_file.assert("2");
 
-    fn temp_file(name: &str) -> NamedTempFile {
-        assert_ok!(NamedTempFile::new(name))
-    }
+        for action in actions.iter().rev() {
+            assert_ok!(revert(action));
+        }
 
-    fn mkdir<P: PathCreateDir>(path: P) -> P {
-        assert_ok!(path.create_dir_all());
-        path
+        src_file.assert("1");
+        dst_file.assert("3");
+        other_file.assert("2");
+        backup_file.assert(predicates::path::missing());
+        root_dir.child("e").assert(predicates::path::missing());
     }
 
-    fn touch<F: FileTouch>(file: F) -> F {
-        assert_ok!(file.touch());
-        file
+    fn overwrite_options(overwrite: OverwriteMode) -> TransferOptions {
+        TransferOptions {
+            overwrite,
+            ..TransferOptions::default()
+        }
     }
 
-    fn write<F: FileWriteStr>(file: F, data: &str) -> F {
-        assert_ok!(file.write_str(data));
-        file
+    #[cfg(unix)]
+    fn mkfifo(path: ChildPath) -> ChildPath {
+        use std::os::unix::ffi::OsStrExt;
+        let c_path = assert_ok!(std::ffi::CString::new(path.as_os_str().as_bytes()));
+        assert_eq!(unsafe { libc::mkfifo(c_path.as_ptr(), 0o644) }, 0);
+        path
     }
 }
@@ -0,0 +1,167 @@
+use crate::fs::normalize;
+use crate::fs::Action;
+use crate::line::LineReader;
+use crate::path::Error;
+use crate::path::ErrorCause;
+use crate::path::PathWriter;
+use crate::path::PrefixedReader;
+use std::io;
+use std::io::BufRead;
+use std::io::Write;
+use std::path::PathBuf;
+
+const SOURCE: u8 = b'<';
+const MOVED: u8 = b'>';
+const COPIED: u8 = b'=';
+const LINKED: u8 = b'&';
+const SYMLINKED: u8 = b'@';
+const CREATED_DIR: u8 = b'+';
+const REMOVED_DIR: u8 = b'-';
+
+const PREFIXES: &[u8] = &[
+    SOURCE,
+    MOVED,
+    COPIED,
+    LINKED,
+    SYMLINKED,
+    CREATED_DIR,
+    REMOVED_DIR,
+];
+
+// Journal uses the input format for moves (`<src`, `>dst`) and extends it
+// with copies (`<src`, `=dst`), hard links (`<src`, `&dst`),
+// symbolic links (`<src`, `@dst`), created (`+dir`) and removed (`-dir`) directories.
+// Paths are written as absolute, so the journal does not depend on working directory.
+pub struct JournalWriter<W> {
+    inner: PathWriter<W>,
+}
+
+impl<W> JournalWriter<W> {
+    pub fn new(inner: PathWriter<W>) -> Self {
+        Self { inner }
+    }
+}
+
+impl<W: Write> JournalWriter<W> {
+    pub fn write(&mut self, action: &Action) -> io::Result<()> {
+        match action {
+            // Replaced files are restored by moving them back.
+            Action::Move(src, dst) | Action::Replace(src, dst) => {
+                self.inner.write(SOURCE, &normalize(src))?;
+                self.inner.write(MOVED, &normalize(dst))
+            }
+            Action::Copy(src, dst) => {
+                self.inner.write(SOURCE, &normalize(src))?;
+                self.inner.write(COPIED, &normalize(dst))
+            }
+            Action::Link(src, dst) => {
+                self.inner.write(SOURCE, &normalize(src))?;
+                self.inner.write(LINKED, &normalize(dst))
+            }
+            Action::Symlink(src, dst) => {
+                self.inner.write(SOURCE, &normalize(src))?;
+                self.inner.write(SYMLINKED, &normalize(dst))
+            }
+            Action::CreateDir(path) => self.inner.write(CREATED_DIR, &normalize(path)),
+            Action::RemoveDir(path) => self.inner.write(REMOVED_DIR, &normalize(path)),
+        }
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+pub struct JournalReader<R> {
+    inner: PrefixedReader<R>,
+    src: Option<PathBuf>,
+}
+
+impl<R> JournalReader<R> {
+    pub fn new(inner: LineReader<R>) -> Self {
+        Self {
+            inner: PrefixedReader::new(inner, PREFIXES, "read journal line"),
+            src: None,
+        }
+    }
+}
+
+impl<R: BufRead> JournalReader<R> {
+    pub fn read(&mut self) -> Result<Option<Action>, Error> {
+        let src = &mut self.src;
+
+        self.inner.read(|prefix, path| {
+            let path = PathBuf::from(path);
+
+            let action = match prefix {
+                SOURCE => {
+                    src.replace(path);
+                    return Ok(None); // Wait for the next dst path
+                }
+                CREATED_DIR => Action::CreateDir(path),
+                REMOVED_DIR => Action::RemoveDir(path),
+                _ => match src.take() {
+                    Some(src) if prefix == MOVED => Action::Move(src, path),
+                    Some(src) if prefix == COPIED => Action::Copy(src, path),
+                    Some(src) if prefix == LINKED => Action::Link(src, path),
+                    Some(src) => Action::Symlink(src, path),
+                    None => return Err(ErrorCause::NoSourcePath),
+                },
+            };
+
+            Ok(Some(action))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::line::Separator;
+    use bstr::ByteSlice;
+    use claim::assert_err;
+    use claim::assert_ok;
+    use claim::assert_ok_eq;
+
+    #[test]
+    fn write_and_read() {
+        let actions = vec![
+            Action::CreateDir(PathBuf::from("/a")),
+            Action::Move(PathBuf::from("/b"), PathBuf::from("/a/b")),
+            Action::Copy(PathBuf::from("/c"), PathBuf::from("/a/c")),
+            Action::Link(PathBuf::from("/c"), PathBuf::from("/a/d")),
+            Action::Symlink(PathBuf::from("/c"), PathBuf::from("/a/e")),
+            Action::RemoveDir(PathBuf::from("/d")),
+        ];
+
+        let mut output = Vec::new();
+        let mut writer = JournalWriter::new(PathWriter::new(&mut output, Separator::Newline));
+        for action in &actions {
+            assert_ok!(writer.write(action));
+        }
+
+        assert_eq!(
+            output.as_bstr(),
+            "+/a\n</b\n>/a/b\n</c\n=/a/c\n</c\n&/a/d\n</c\n@/a/e\n-/d\n"
+        );
+
+        let mut reader = JournalReader::new(LineReader::new(&output[..], Separator::Newline));
+        for action in actions {
+            assert_ok_eq!(reader.read(), Some(action));
+        }
+        assert_ok_eq!(reader.read(), None);
+    }
+
+    #[test]
+    fn read_invalid() {
+        let input = "<a\n>b\n=c";
+        let mut reader = JournalReader::new(LineReader::new(input.as_bytes(), Separator::Newline));
+
+        assert_ok!(reader.read());
+        let error = assert_err!(reader.read());
+        assert_eq!(
+            error.to_string(),
+            "Failed to read journal line #3: =c\nNo previous source path"
+        );
+    }
+}
@@ -1,28 +1,52 @@
 use crate::cli::Cli;
-use crate::fs::transfer;
+use crate::exec::Executor;
+use crate::exec::FailedInstruction;
+use crate::exec::Failures;
 use crate::fs::TransferMode;
+use crate::fs::TransferOptions;
+use crate::journal::JournalReader;
+use crate::journal::JournalWriter;
 use crate::line::LineReader;
 use crate::line::Separator;
 use crate::path::PathReader;
+use crate::path::PathWriter;
+use crate::plan::FailedSteps;
+use crate::plan::Plan;
+use crate::sim::Simulator;
 use crate::verbose::Logger;
 use atty::Stream;
 use clap::Parser;
 use std::error::Error;
+use std::fs::File;
+use std::fs::OpenOptions;
 use std::io;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::path::Path;
 use std::process;
 use termcolor::ColorChoice;
 use termcolor::StandardStream;
+use termcolor::WriteColor;
 
+mod checksum;
 mod cli;
+mod exec;
 mod fs;
+mod journal;
 mod line;
 mod path;
+mod plan;
+mod sim;
+mod sys;
+#[cfg(test)]
+mod testing;
 mod verbose;
 
 fn main() {
     if let Err(error) = try_main() {
         eprintln!("error: {}", error);
-        process::exit(1);
+        process::exit(if error.is::<Failures>() { 3 } else { 1 });
     }
 }
 
@@ -35,12 +59,36 @@ fn try_main() -> Result<(), Box<dyn Error>> {
         Separator::Newline
     };
 
+    if cli.symlink && !sys::HAS_SYMLINKS {
+        return Err("Symbolic links are not supported on this platform".into());
+    }
+
     let mode = if cli.copy {
         TransferMode::Copy
+    } else if cli.link {
+        TransferMode::Link
+    } else if cli.symlink {
+        TransferMode::Symlink
     } else {
         TransferMode::Move
     };
 
+    let options = TransferOptions {
+        overwrite: cli.overwrite,
+        reflink: cli.reflink,
+        sparse: cli.sparse,
+        preserve: cli.preserve.clone(),
+        ignore_preserve_errors: cli.ignore_preserve_errors,
+        special: cli.special.clone(),
+        relative: cli.relative,
+        expand_dirs: cli.expand_dirs,
+        dereference: cli.dereference,
+        no_copy_fallback: cli.no_copy_fallback,
+        sync: cli.sync,
+        verify: cli.verify,
+        keep_replaced: cli.atomic || cli.journal.is_some(),
+    };
+
     let colors = if atty::is(Stream::Stdout) {
         ColorChoice::Auto
     } else {
@@ -52,27 +100,157 @@ fn try_main() -> Result<(), Box<dyn Error>> {
 
     let line_reader = LineReader::new(stdin.lock(), separator);
     let mut path_reader = PathReader::new(line_reader);
-    let mut logger = Logger::new(stdout.lock());
 
-    while let Some((src, dst)) = path_reader.read()? {
-        if cli.verbose {
-            logger.begin(src, dst, mode)?;
+    let logger = if cli.verbose || cli.dry_run {
+        Some(Logger::new(stdout.lock(), cli.dry_run))
+    } else {
+        None
+    };
+
+    let simulator = if cli.dry_run {
+        Some(Simulator::new())
+    } else {
+        None
+    };
+
+    let journal = match &cli.journal {
+        Some(path) => {
+            // Journal of a previous run is never overwritten, so it can still be undone.
+            let file = OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(path)
+                .map_err(|error| {
+                    format!(
+                        "Cannot create journal '{}': {}",
+                        path.to_string_lossy(),
+                        error
+                    )
+                })?;
+            let writer = BufWriter::new(file);
+            Some(JournalWriter::new(PathWriter::new(writer, separator)))
         }
+        None => None,
+    };
+
+    let failed = match &cli.failed {
+        Some(path) => {
+            let writer = BufWriter::new(File::create(path)?);
+            Some(PathWriter::new(writer, separator))
+        }
+        None => None,
+    };
+
+    let mut executor = Executor::new(
+        options.clone(),
+        logger,
+        simulator,
+        journal,
+        failed,
+        cli.atomic,
+    );
+
+    if let Some(path) = &cli.undo {
+        return undo(&mut executor, path, separator);
+    }
+
+    match execute(&cli, &mut executor, &mut path_reader, mode, &options) {
+        Ok(()) => executor.commit(),
+        Err(error) if cli.atomic => Err(executor.rollback(error)),
+        Err(error) => Err(error),
+    }
+}
+
+fn execute<R: BufRead, W: WriteColor>(
+    cli: &Cli,
+    executor: &mut Executor<W>,
+    path_reader: &mut PathReader<R>,
+    mode: TransferMode,
+    options: &TransferOptions,
+) -> Result<(), Box<dyn Error>> {
+    let mut failures = Failures::default();
 
-        match transfer(src, dst, mode) {
-            Ok(()) => {
-                if cli.verbose {
-                    logger.success()?;
+    if cli.stream {
+        while let Some((src, dst)) = path_reader.read()? {
+            let result = executor.execute(src, dst, mode);
+            if cli.keep_going {
+                report_failure(failures.record(result, path_reader.line()));
+            } else {
+                result?;
+            }
+        }
+    } else {
+        let mut plan = Plan::read(path_reader, mode)?;
+        plan.check_conflicts(options)?;
+        plan.sort();
+
+        if !cli.dry_run {
+            for (step, cause) in plan.remove_invalid(options) {
+                if cli.keep_going {
+                    executor.write_failed(&step.src, &step.dst)?;
+                    report_failure(failures.record(Err(cause), step.line));
+                } else {
+                    return Err(plan::Error::Invalid {
+                        cause,
+                        line: step.line,
+                    }
+                    .into());
                 }
             }
-            Err(error) => {
-                if cli.verbose {
-                    logger.failure()?;
+        }
+
+        let mut failed_steps = FailedSteps::default();
+
+        for step in plan.steps() {
+            if cli.keep_going {
+                let result = match failed_steps.check(step) {
+                    Ok(()) => executor.execute(&step.src, &step.dst, step.mode),
+                    Err(skipped) => {
+                        executor.write_failed(&step.src, &step.dst)?;
+                        Err(skipped.into())
+                    }
+                };
+                if result.is_err() {
+                    failed_steps.insert(step);
                 }
-                return Err(error.into());
+                report_failure(failures.record(result, step.line));
+            } else {
+                executor.execute(&step.src, &step.dst, step.mode)?;
             }
         }
     }
 
+    if cli.keep_going {
+        let count = failures.into_result()?;
+        eprintln!("All {} operations succeeded", count);
+    }
+
+    Ok(())
+}
+
+// Failures are reported immediately, the summary is reported at the end.
+fn report_failure(failed: Option<FailedInstruction>) {
+    if let Some(failed) = failed {
+        eprintln!("error: {}", failed);
+    }
+}
+
+fn undo<W: WriteColor>(
+    executor: &mut Executor<W>,
+    path: &Path,
+    separator: Separator,
+) -> Result<(), Box<dyn Error>> {
+    let reader = LineReader::new(BufReader::new(File::open(path)?), separator);
+    let mut journal_reader = JournalReader::new(reader);
+    let mut actions = Vec::new();
+
+    while let Some(action) = journal_reader.read()? {
+        actions.push(action);
+    }
+
+    for action in actions.iter().rev() {
+        executor.revert(action)?;
+    }
+
     Ok(())
 }
@@ -1,31 +1,25 @@
 use crate::line::LineReader;
+use crate::line::Separator;
 use bstr::ByteSlice;
 use std::ffi::OsString;
 use std::io;
 use std::io::BufRead;
+use std::io::Write;
 use std::path::Path;
 
 const MAX_LINE: usize = 1024 * 1024;
 const MAX_PREVIEW: usize = 30;
 
-#[derive(Copy, Clone)]
-enum PathKind {
-    Source,
-    Dest,
-}
-
-fn parse_line(line: &[u8]) -> Result<(PathKind, OsString), ErrorCause> {
-    if let Some((prefix, path)) = line.split_first() {
-        let kind = match prefix {
-            b'<' => PathKind::Source,
-            b'>' => PathKind::Dest,
-            _ => return Err(ErrorCause::InvalidPrefix),
-        };
+fn parse_line(line: &[u8], prefixes: &'static [u8]) -> Result<(u8, OsString), ErrorCause> {
+    if let Some((&prefix, path)) = line.split_first() {
+        if !prefixes.contains(&prefix) {
+            return Err(ErrorCause::InvalidPrefix(prefixes));
+        }
         if path.is_empty() {
             return Err(ErrorCause::EmptyPath);
         }
         match path.to_os_str() {
-            Ok(path) => Ok((kind, path.to_owned())),
+            Ok(path) => Ok((prefix, path.to_owned())),
             Err(_) => Err(ErrorCause::InvalidEncoding),
         }
     } else {
@@ -48,12 +42,25 @@ fn preview_line(line: &[u8]) -> String {
     preview
 }
 
+fn format_prefixes(prefixes: &[u8]) -> String {
+    let prefixes = prefixes
+        .iter()
+        .map(|&prefix| format!("'{}'", prefix as char))
+        .collect::<Vec<_>>();
+
+    match prefixes.split_last() {
+        Some((last, [])) => last.clone(),
+        Some((last, rest)) => format!("{} or {}", rest.join(", "), last),
+        None => String::new(),
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
-enum ErrorCause {
+pub enum ErrorCause {
     #[error("Invalid UTF-8 encoding")]
     InvalidEncoding,
-    #[error("Invalid line prefix, expected '<' or '>'")]
-    InvalidPrefix,
+    #[error("Invalid line prefix, expected {}", format_prefixes(.0))]
+    InvalidPrefix(&'static [u8]),
     #[error("Empty line")]
     EmptyLine,
     #[error("Empty path")]
@@ -67,33 +74,44 @@ enum ErrorCause {
 }
 
 #[derive(Debug, thiserror::Error)]
-#[error("Failed to process line #{line}: {preview}\n{cause}")]
+#[error("Failed to {context} #{line}: {preview}\n{cause}")]
 pub struct Error {
     cause: ErrorCause,
+    context: &'static str,
     line: usize,
     preview: String,
 }
 
-pub struct PathReader<R> {
+// Reads lines consisting of a prefix and a path.
+// Shared by the input and journal format, which differ only in allowed prefixes.
+pub struct PrefixedReader<R> {
     inner: LineReader<R>,
-    src: Option<OsString>,
-    dst: Option<OsString>,
+    prefixes: &'static [u8],
+    context: &'static str,
     line: usize,
 }
 
-impl<R> PathReader<R> {
-    pub fn new(inner: LineReader<R>) -> Self {
+impl<R> PrefixedReader<R> {
+    pub fn new(inner: LineReader<R>, prefixes: &'static [u8], context: &'static str) -> Self {
         Self {
             inner,
-            src: None,
-            dst: None,
+            prefixes,
+            context,
             line: 0,
         }
     }
+
+    pub fn line(&self) -> usize {
+        self.line
+    }
 }
 
-impl<R: BufRead> PathReader<R> {
-    pub fn read(&mut self) -> Result<Option<(&Path, &Path)>, Error> {
+impl<R: BufRead> PrefixedReader<R> {
+    // Passes parsed lines to the handler until it returns a value.
+    pub fn read<T>(
+        &mut self,
+        mut handle: impl FnMut(u8, OsString) -> Result<Option<T>, ErrorCause>,
+    ) -> Result<Option<T>, Error> {
         loop {
             self.line += 1;
 
@@ -103,58 +121,129 @@ impl<R: BufRead> PathReader<R> {
                 Err(error) => {
                     return Err(Error {
                         cause: ErrorCause::IoError(error),
+                        context: self.context,
                         line: self.line,
                         preview: String::new(),
                     });
                 }
             };
 
-            if buffer.len() >= MAX_LINE {
-                return Err(Error {
-                    cause: ErrorCause::LineOverflow,
-                    line: self.line,
-                    preview: preview_line(buffer),
-                });
-            }
+            let result = if buffer.len() >= MAX_LINE {
+                Err(ErrorCause::LineOverflow)
+            } else {
+                parse_line(buffer, self.prefixes).and_then(|(prefix, path)| handle(prefix, path))
+            };
 
-            match parse_line(buffer) {
-                Ok((PathKind::Source, path)) => {
-                    self.src.replace(path);
-                    continue; // Wait for the next dst path
-                }
-                Ok((PathKind::Dest, path)) => {
-                    self.dst.replace(path);
-                }
+            match result {
+                Ok(Some(value)) => return Ok(Some(value)),
+                Ok(None) => continue,
                 Err(cause) => {
                     return Err(Error {
                         cause,
+                        context: self.context,
                         line: self.line,
                         preview: preview_line(buffer),
                     })
                 }
             }
+        }
+    }
+}
 
-            match (&self.src, &self.dst) {
-                (Some(src), Some(dst)) => {
-                    return Ok(Some((Path::new(src), Path::new(dst))));
-                }
-                (None, Some(_)) => {
-                    return Err(Error {
-                        cause: ErrorCause::NoSourcePath,
-                        line: self.line,
-                        preview: preview_line(buffer),
-                    })
-                }
-                _ => unreachable!("Expected dst instruction to be present"),
+pub struct PathReader<R> {
+    inner: PrefixedReader<R>,
+    src: Option<OsString>,
+    dst: Option<OsString>,
+}
+
+impl<R> PathReader<R> {
+    pub fn new(inner: LineReader<R>) -> Self {
+        Self {
+            inner: PrefixedReader::new(inner, b"<>", "process line"),
+            src: None,
+            dst: None,
+        }
+    }
+
+    pub fn line(&self) -> usize {
+        self.inner.line()
+    }
+}
+
+impl<R: BufRead> PathReader<R> {
+    pub fn read(&mut self) -> Result<Option<(&Path, &Path)>, Error> {
+        let src = &mut self.src;
+        let dst = &mut self.dst;
+
+        let found = self.inner.read(|prefix, path| {
+            if prefix == b'<' {
+                src.replace(path);
+                return Ok(None); // Wait for the next dst path
             }
+            if src.is_none() {
+                return Err(ErrorCause::NoSourcePath);
+            }
+            dst.replace(path);
+            Ok(Some(()))
+        })?;
+
+        match (found, &self.src, &self.dst) {
+            (Some(()), Some(src), Some(dst)) => Ok(Some((Path::new(src), Path::new(dst)))),
+            _ => Ok(None),
         }
     }
 }
 
+pub struct PathWriter<W> {
+    inner: W,
+    separator: Separator,
+}
+
+impl<W> PathWriter<W> {
+    pub fn new(inner: W, separator: Separator) -> Self {
+        Self { inner, separator }
+    }
+}
+
+impl<W: Write> PathWriter<W> {
+    pub fn write(&mut self, prefix: u8, path: &Path) -> io::Result<()> {
+        let bytes = match <[u8]>::from_path(path) {
+            Some(bytes) => bytes,
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Path '{}' is not valid UTF-8", path.to_string_lossy()),
+                ))
+            }
+        };
+
+        if bytes.contains(&self.separator.as_byte()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Path '{}' contains line delimiter", path.to_string_lossy()),
+            ));
+        }
+
+        self.inner.write_all(&[prefix])?;
+        self.inner.write_all(bytes)?;
+        self.inner.write_all(&[self.separator.as_byte()])
+    }
+
+    pub fn write_pair(&mut self, src: &Path, dst: &Path) -> io::Result<()> {
+        self.write(b'<', src)?;
+        self.write(b'>', dst)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::line::Separator;
+    use claim::assert_err;
+    use claim::assert_ok;
     use claim::assert_ok_eq;
     use std::path::Path;
     use test_case::test_case;
@@ -165,7 +254,28 @@ mod tests {
         let line_reader = LineReader::new(input.as_bytes(), separator);
         let mut reader = PathReader::new(line_reader);
         assert_ok_eq!(reader.read(), Some((Path::new("a"), Path::new("bc"))));
+        assert_eq!(reader.line(), 3);
         assert_ok_eq!(reader.read(), Some((Path::new("a"), Path::new("def"))));
+        assert_eq!(reader.line(), 4);
         assert_ok_eq!(reader.read(), None);
     }
+
+    #[test_case("<a\nb",  "Failed to process line #2: b\nInvalid line prefix, expected '<' or '>'" ; "invalid prefix")]
+    #[test_case("<a\n<",  "Failed to process line #2: <\nEmpty path"                               ; "empty path")]
+    #[test_case(">a",     "Failed to process line #1: >a\nNo previous source path"                  ; "no source path")]
+    fn reader_invalid(input: &str, message: &str) {
+        let line_reader = LineReader::new(input.as_bytes(), Separator::Newline);
+        let mut reader = PathReader::new(line_reader);
+        let error = assert_err!(reader.read());
+        assert_eq!(error.to_string(), message);
+    }
+
+    #[test_case(Separator::Newline, "<a\n>bc\n" ; "newline")]
+    #[test_case(Separator::Null,    "<a\0>bc\0" ; "null")]
+    fn writer(separator: Separator, output: &str) {
+        let mut writer = PathWriter::new(Vec::new(), separator);
+        assert_ok!(writer.write_pair(Path::new("a"), Path::new("bc")));
+        assert_err!(writer.write(b'>', Path::new("d\ne\0")));
+        assert_eq!(writer.inner.as_bstr(), output);
+    }
 }
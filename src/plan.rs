@@ -0,0 +1,707 @@
+use crate::fs::canonicalize_existing;
+use crate::fs::normalize;
+use crate::fs::temp_path;
+use crate::fs::FileType;
+use crate::fs::TransferMode;
+use crate::fs::TransferOptions;
+use crate::path;
+use crate::path::PathReader;
+use crate::sim::Simulator;
+use crate::sys;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::BufRead;
+use std::mem;
+use std::path::Path;
+use std::path::PathBuf;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Invalid instruction on line #{line}\n{cause}")]
+    Invalid {
+        cause: Box<dyn std::error::Error>,
+        line: usize,
+    },
+    #[error("Conflicting output paths{}", .0.iter().map(|c| format!("\n{}", c)).collect::<String>())]
+    Conflicts(Vec<Conflict>),
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Path '{}' was not transferred, it depends on the failed instruction on line #{line}", .path.to_string_lossy())]
+pub struct Skipped {
+    path: PathBuf,
+    line: usize,
+}
+
+#[derive(Debug)]
+pub struct Conflict {
+    path: PathBuf,
+    lines: Vec<usize>,
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "'{}' on lines ", self.path.to_string_lossy())?;
+
+        for (index, line) in self.lines.iter().enumerate() {
+            if index > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "#{}", line)?;
+        }
+
+        Ok(())
+    }
+}
+
+// Identifies an output path, so that different paths to the same file are equal.
+#[derive(PartialEq, Eq, Hash)]
+enum PathKey {
+    Existing((u64, u64)),
+    Missing(PathBuf),
+}
+
+impl From<&Path> for PathKey {
+    fn from(path: &Path) -> Self {
+        if let Some(id) = path.symlink_metadata().ok().as_ref().and_then(sys::file_id) {
+            return PathKey::Existing(id);
+        }
+
+        PathKey::Missing(canonicalize_existing(path))
+    }
+}
+
+pub struct Step {
+    pub src: PathBuf,
+    pub dst: PathBuf,
+    pub mode: TransferMode,
+    pub line: usize,
+}
+
+impl Step {
+    // Input directories are merged into the output directory, so they may share it.
+    fn merges(&self, options: &TransferOptions) -> bool {
+        options.source_type(&self.src, self.mode) == FileType::Dir
+            && (self.mode != TransferMode::Symlink || options.expand_dirs)
+    }
+}
+
+pub struct Plan {
+    steps: Vec<Step>,
+}
+
+impl Plan {
+    pub fn read<R: BufRead>(
+        reader: &mut PathReader<R>,
+        mode: TransferMode,
+    ) -> Result<Self, path::Error> {
+        let mut steps = Vec::new();
+
+        while let Some((src, dst)) = reader.read()? {
+            steps.push(Step {
+                src: src.to_path_buf(),
+                dst: dst.to_path_buf(),
+                mode,
+                line: reader.line(),
+            });
+        }
+
+        Ok(Self { steps })
+    }
+
+    // Orders steps so that each existing source path is read before it gets
+    // overwritten by another step. Cycles (including swaps) are broken
+    // by transferring one of their sources to a temporary path first.
+    pub fn sort(&mut self) {
+        let count = self.steps.len();
+        let srcs = self
+            .steps
+            .iter()
+            .map(|step| normalize(&step.src))
+            .collect::<Vec<_>>();
+
+        let mut src_indices = HashMap::<&Path, Vec<usize>>::new();
+        for (index, src) in srcs.iter().enumerate() {
+            src_indices.entry(src).or_default().push(index);
+        }
+
+        let mut edges = Vec::new();
+        for (dst_index, step) in self.steps.iter().enumerate() {
+            if let Some(indices) = src_indices.get(normalize(&step.dst).as_path()) {
+                for &src_index in indices {
+                    if src_index == dst_index {
+                        continue;
+                    }
+                    // Source path which does not exist yet has to be created
+                    // by the other step first.
+                    if FileType::from(srcs[src_index].as_path()) == FileType::Unknown {
+                        edges.push((dst_index, src_index));
+                    } else {
+                        edges.push((src_index, dst_index));
+                    }
+                }
+            }
+        }
+
+        let mut graph = Graph::new(count, &edges);
+
+        let mut steps = mem::take(&mut self.steps)
+            .into_iter()
+            .map(Some)
+            .collect::<Vec<_>>();
+
+        loop {
+            while let Some(index) = graph.pop() {
+                self.steps
+                    .push(steps[index].take().expect("Expected step to be present"));
+            }
+
+            if let Some(index) = graph.find_cycle() {
+                let step = steps[index].as_mut().expect("Expected step to be present");
+                let temp = match step.dst.file_name() {
+                    Some(_) => temp_path(&step.dst),
+                    None => temp_path(&normalize(&step.dst)),
+                };
+
+                self.steps.push(Step {
+                    src: mem::replace(&mut step.src, temp.clone()),
+                    dst: temp,
+                    mode: step.mode,
+                    line: step.line,
+                });
+
+                step.mode = TransferMode::Move;
+                graph.detach(index);
+            } else {
+                break;
+            }
+        }
+    }
+
+    pub fn steps(&self) -> &[Step] {
+        &self.steps
+    }
+
+    pub fn check_conflicts(&self, options: &TransferOptions) -> Result<(), Error> {
+        let mut groups = HashMap::<PathKey, Vec<&Step>>::new();
+
+        for step in &self.steps {
+            groups
+                .entry(PathKey::from(step.dst.as_path()))
+                .or_default()
+                .push(step);
+        }
+
+        let mut conflicts = groups
+            .into_values()
+            .filter(|steps| steps.len() > 1 && !steps.iter().all(|step| step.merges(options)))
+            .map(|steps| Conflict {
+                path: steps[0].dst.clone(),
+                lines: steps.iter().map(|step| step.line).collect(),
+            })
+            .collect::<Vec<_>>();
+
+        if conflicts.is_empty() {
+            Ok(())
+        } else {
+            conflicts.sort_by_key(|conflict| conflict.lines[0]);
+            Err(Error::Conflicts(conflicts))
+        }
+    }
+
+    // Removes steps which would fail, so that the remaining ones can still be performed.
+    // Later steps are validated as if the removed ones were never performed.
+    pub fn remove_invalid(
+        &mut self,
+        options: &TransferOptions,
+    ) -> Vec<(Step, Box<dyn std::error::Error>)> {
+        let mut simulator = Simulator::new();
+        let mut failed_steps = FailedSteps::default();
+        let mut invalid = Vec::new();
+
+        for step in mem::take(&mut self.steps) {
+            let result = match failed_steps.check(&step) {
+                Ok(()) => simulator
+                    .transfer(&step.src, &step.dst, step.mode, options)
+                    .map_err(Into::into),
+                Err(skipped) => Err(skipped.into()),
+            };
+
+            match result {
+                Ok(()) => self.steps.push(step),
+                Err(cause) => {
+                    failed_steps.insert(&step);
+                    invalid.push((step, cause));
+                }
+            }
+        }
+
+        invalid
+    }
+}
+
+// Remembers paths of failed steps, so that steps depending on them can be skipped.
+#[derive(Default)]
+pub struct FailedSteps {
+    srcs: HashMap<PathBuf, usize>,
+    dsts: HashMap<PathBuf, usize>,
+}
+
+impl FailedSteps {
+    pub fn insert(&mut self, step: &Step) {
+        self.srcs.entry(normalize(&step.src)).or_insert(step.line);
+        self.dsts.entry(normalize(&step.dst)).or_insert(step.line);
+    }
+
+    // Step depends on a failed one when it would overwrite its unmoved input path
+    // (e.g. `1 -> 2` after `2 -> 3`) or read from its missing output path
+    // (e.g. the temporary path of a cycle).
+    pub fn check(&self, step: &Step) -> Result<(), Skipped> {
+        let line = self
+            .srcs
+            .get(&normalize(&step.dst))
+            .or_else(|| self.dsts.get(&normalize(&step.src)));
+
+        match line {
+            Some(&line) => Err(Skipped {
+                path: step.src.clone(),
+                line,
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+// Dependency graph where an edge means "has to be executed before".
+struct Graph {
+    successors: Vec<Vec<usize>>,
+    predecessors: Vec<Vec<usize>>,
+    degrees: Vec<usize>,
+    detached: Vec<bool>,
+    done: Vec<bool>,
+    queue: BinaryHeap<Reverse<usize>>,
+    // Nodes before the cursor are never pending again.
+    cursor: usize,
+    // Number of the last search which visited each node.
+    visits: Vec<usize>,
+    search: usize,
+}
+
+impl Graph {
+    fn new(count: usize, edges: &[(usize, usize)]) -> Self {
+        let mut successors = vec![Vec::new(); count];
+        let mut predecessors = vec![Vec::new(); count];
+
+        for &(from, to) in edges {
+            successors[from].push(to);
+            predecessors[to].push(from);
+        }
+
+        let degrees = predecessors.iter().map(Vec::len).collect::<Vec<_>>();
+        let queue = (0..count)
+            .filter(|&index| degrees[index] == 0)
+            .map(Reverse)
+            .collect();
+
+        Self {
+            successors,
+            predecessors,
+            degrees,
+            detached: vec![false; count],
+            done: vec![false; count],
+            queue,
+            cursor: 0,
+            visits: vec![0; count],
+            search: 0,
+        }
+    }
+
+    // Returns the lowest node without unresolved predecessors.
+    fn pop(&mut self) -> Option<usize> {
+        let Reverse(index) = self.queue.pop()?;
+        self.done[index] = true;
+
+        if !self.detached[index] {
+            self.release(index);
+        }
+
+        Some(index)
+    }
+
+    // Removes outgoing edges of a node which still waits for its predecessors.
+    fn detach(&mut self, index: usize) {
+        self.detached[index] = true;
+        self.release(index);
+    }
+
+    fn release(&mut self, index: usize) {
+        for &successor in &self.successors[index] {
+            self.degrees[successor] -= 1;
+            if self.degrees[successor] == 0 {
+                self.queue.push(Reverse(successor));
+            }
+        }
+    }
+
+    fn is_pending(&self, index: usize) -> bool {
+        !self.done[index] && !self.detached[index]
+    }
+
+    // Returns a node on a cycle, if there is any.
+    fn find_cycle(&mut self) -> Option<usize> {
+        while self.cursor < self.done.len() && !self.is_pending(self.cursor) {
+            self.cursor += 1;
+        }
+
+        let mut index = self.cursor;
+        if index == self.done.len() {
+            return None;
+        }
+
+        self.search += 1;
+
+        // Each pending node has a pending predecessor, so we must end up in a cycle.
+        while self.visits[index] != self.search {
+            self.visits[index] = self.search;
+            index = *self.predecessors[index]
+                .iter()
+                .find(|&&predecessor| self.is_pending(predecessor))
+                .expect("Expected pending node to have a pending predecessor");
+        }
+
+        Some(index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::transfer;
+    use crate::line::LineReader;
+    use crate::line::Separator;
+    use crate::testing::mkdir;
+    use crate::testing::touch;
+    use crate::testing::write;
+    use assert_fs::prelude::*;
+    use assert_fs::TempDir;
+    use claim::assert_err;
+    use claim::assert_ok;
+    use test_case::test_case;
+
+    #[test]
+    fn read() {
+        let input = "<a\n>b\n<c\n>d\n>e";
+        let mut reader = PathReader::new(LineReader::new(input.as_bytes(), Separator::Newline));
+        let plan = assert_ok!(Plan::read(&mut reader, TransferMode::Copy));
+
+        let steps = plan
+            .steps()
+            .iter()
+            .map(|step| (step.src.to_str(), step.dst.to_str(), step.line))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            steps,
+            vec![
+                (Some("a"), Some("b"), 2),
+                (Some("c"), Some("d"), 4),
+                (Some("c"), Some("e"), 5)
+            ]
+        );
+    }
+
+    #[test]
+    fn remove_invalid() {
+        let root_dir = assert_ok!(TempDir::new());
+        let file = root_dir.child("a");
+        assert_ok!(file.touch());
+
+        let input = format!(
+            "<{0}/a\n>{0}/b\n<{0}/b\n>{0}/c\n<{0}/a\n>{0}/d\n<{0}/c\n>{0}/e",
+            root_dir.path().to_string_lossy()
+        );
+        let mut reader = PathReader::new(LineReader::new(input.as_bytes(), Separator::Newline));
+        let mut plan = assert_ok!(Plan::read(&mut reader, TransferMode::Move));
+        let invalid = plan.remove_invalid(&TransferOptions::default());
+
+        let invalid = invalid
+            .iter()
+            .map(|(step, cause)| (step.line, cause.to_string()))
+            .collect::<Vec<_>>();
+        let steps = plan
+            .steps()
+            .iter()
+            .map(|step| step.line)
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            invalid,
+            vec![(
+                6,
+                format!(
+                    "Path '{}' does not exist or you don't have access",
+                    file.path().to_string_lossy()
+                )
+            )]
+        );
+        assert_eq!(steps, vec![2, 4, 8]);
+
+        file.assert(predicates::path::is_file());
+    }
+
+    #[test]
+    fn remove_invalid_chain() {
+        let root_dir = assert_ok!(TempDir::new());
+        write(root_dir.child("1"), "one");
+        write(root_dir.child("2"), "two");
+        mkdir(root_dir.child("3"));
+
+        let mut plan = read_in(&root_dir, "<1\n>2\n<2\n>3", TransferMode::Move);
+        plan.sort();
+        let invalid = plan.remove_invalid(&TransferOptions::default());
+
+        let invalid = invalid
+            .iter()
+            .map(|(step, cause)| (step.line, cause.to_string()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            invalid,
+            vec![
+                (
+                    4,
+                    format!(
+                        "Cannot to overwrite directory '{0}/3' with file '{0}/2'",
+                        root_dir.path().to_string_lossy()
+                    )
+                ),
+                (
+                    2,
+                    format!(
+                        "Path '{}/1' was not transferred, it depends on the failed instruction on line #4",
+                        root_dir.path().to_string_lossy()
+                    )
+                )
+            ]
+        );
+        assert!(plan.steps().is_empty());
+    }
+
+    #[test]
+    fn failed_steps_swap() {
+        let root_dir = assert_ok!(TempDir::new());
+        write(root_dir.child("a"), "1");
+        write(root_dir.child("b"), "2");
+
+        let mut plan = read_in(&root_dir, "<c\n>d\n<a\n>b\n<b\n>a", TransferMode::Move);
+        plan.sort();
+        let steps = plan.steps();
+
+        assert_eq!(
+            lines(&plan, &root_dir),
+            vec![
+                (2, "c", "d"),
+                (4, "a", ".b.sren0"),
+                (6, "b", "a"),
+                (4, ".b.sren0", "b")
+            ]
+        );
+
+        // First half of the swap failed, so its input path must not be overwritten.
+        let mut failed_steps = FailedSteps::default();
+        failed_steps.insert(&steps[1]);
+
+        let error = assert_err!(failed_steps.check(&steps[2]));
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "Path '{}/b' was not transferred, it depends on the failed instruction on line #4",
+                root_dir.path().to_string_lossy()
+            )
+        );
+        failed_steps.insert(&steps[2]);
+
+        assert_err!(failed_steps.check(&steps[3]));
+        assert_ok!(failed_steps.check(&steps[0]));
+    }
+
+    #[test]
+    fn check_conflicts() {
+        let root_dir = assert_ok!(TempDir::new());
+        mkdir(root_dir.child("x"));
+        touch(root_dir.child("y"));
+
+        let input = "<a\n>x/../z\n<b\n>x\n<c\n>z\n<d\n>y\n<e\n>x/.\n<f\n>./y\n<g\n>w\n<h\n>z";
+        let mut reader = PathReader::new(LineReader::new(input.as_bytes(), Separator::Newline));
+        let mut plan = assert_ok!(Plan::read(&mut reader, TransferMode::Move));
+
+        for step in &mut plan.steps {
+            step.dst = root_dir.join(&step.dst);
+        }
+
+        let error = assert_err!(plan.check_conflicts(&TransferOptions::default()));
+
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "Conflicting output paths\n\
+                 '{0}/x/../z' on lines #2, #6, #16\n\
+                 '{0}/x' on lines #4, #10\n\
+                 '{0}/y' on lines #8, #12",
+                root_dir.path().to_string_lossy()
+            )
+        );
+    }
+
+    #[test_case(TransferMode::Move, false ; "moving")]
+    #[test_case(TransferMode::Copy, false ; "copying")]
+    #[test_case(TransferMode::Symlink, true ; "symlinking")]
+    fn check_conflicts_merged_dirs(mode: TransferMode, conflicts: bool) {
+        let root_dir = assert_ok!(TempDir::new());
+        touch(root_dir.child("a/c"));
+        touch(root_dir.child("b/d"));
+
+        let input = format!(
+            "<{0}/a\n>{0}/x\n<{0}/b\n>{0}/x",
+            root_dir.path().to_string_lossy()
+        );
+        let mut reader = PathReader::new(LineReader::new(input.as_bytes(), Separator::Newline));
+        let plan = assert_ok!(Plan::read(&mut reader, mode));
+        let options = TransferOptions::default();
+
+        assert_eq!(plan.check_conflicts(&options).is_err(), conflicts);
+        assert_ok!(plan.check_conflicts(&TransferOptions {
+            expand_dirs: true,
+            ..TransferOptions::default()
+        }));
+    }
+
+    #[test_case(TransferMode::Move ; "moving")]
+    #[test_case(TransferMode::Copy ; "copying")]
+    fn sort_swap(mode: TransferMode) {
+        let root_dir = assert_ok!(TempDir::new());
+        let a_file = write(root_dir.child("a"), "1");
+        let b_file = write(root_dir.child("b"), "2");
+
+        let plan = sort(&root_dir, "<a\n>b\n<b\n>a", mode);
+        let steps = lines(&plan, &root_dir);
+
+        assert_eq!(
+            steps,
+            vec![(2, "a", ".b.sren0"), (4, "b", "a"), (2, ".b.sren0", "b")]
+        );
+        assert_eq!(plan.steps()[0].mode, mode);
+        assert_eq!(plan.steps()[1].mode, mode);
+        assert_eq!(plan.steps()[2].mode, TransferMode::Move);
+
+        execute(&plan);
+
+        a_file.assert("2");
+        b_file.assert("1");
+    }
+
+    #[test]
+    fn sort_shift() {
+        let root_dir = assert_ok!(TempDir::new());
+        let a_file = write(root_dir.child("1"), "a");
+        let b_file = write(root_dir.child("2"), "b");
+        let c_file = write(root_dir.child("3"), "c");
+        let d_file = root_dir.child("4");
+
+        let plan = sort(&root_dir, "<1\n>2\n<2\n>3\n<3\n>4", TransferMode::Move);
+        let steps = lines(&plan, &root_dir);
+
+        assert_eq!(steps, vec![(6, "3", "4"), (4, "2", "3"), (2, "1", "2")]);
+
+        execute(&plan);
+
+        a_file.assert(predicates::path::missing());
+        b_file.assert("a");
+        c_file.assert("b");
+        d_file.assert("c");
+    }
+
+    #[test]
+    fn sort_cycle() {
+        let root_dir = assert_ok!(TempDir::new());
+        let a_file = write(root_dir.child("a"), "1");
+        let b_file = write(root_dir.child("b"), "2");
+        let c_file = write(root_dir.child("c"), "3");
+        let d_file = root_dir.child("d");
+
+        let plan = sort(
+            &root_dir,
+            "<c\n>d\n<a\n>b\n<b\n>c\n<c\n>a",
+            TransferMode::Copy,
+        );
+
+        execute(&plan);
+
+        a_file.assert("3");
+        b_file.assert("1");
+        c_file.assert("2");
+        d_file.assert("3");
+    }
+
+    #[test]
+    fn sort_chain() {
+        let root_dir = assert_ok!(TempDir::new());
+        let a_file = write(root_dir.child("a"), "1");
+        let c_file = root_dir.child("c");
+
+        let plan = sort(&root_dir, "<b\n>c\n<a\n>b", TransferMode::Move);
+        let steps = lines(&plan, &root_dir);
+
+        assert_eq!(steps, vec![(4, "a", "b"), (2, "b", "c")]);
+
+        execute(&plan);
+
+        a_file.assert(predicates::path::missing());
+        c_file.assert("1");
+    }
+
+    fn sort(root_dir: &TempDir, input: &str, mode: TransferMode) -> Plan {
+        let mut plan = read_in(root_dir, input, mode);
+        plan.sort();
+        assert!(plan.remove_invalid(&TransferOptions::default()).is_empty());
+        plan
+    }
+
+    fn read_in(root_dir: &TempDir, input: &str, mode: TransferMode) -> Plan {
+        let mut reader = PathReader::new(LineReader::new(input.as_bytes(), Separator::Newline));
+        let mut plan = assert_ok!(Plan::read(&mut reader, mode));
+
+        for step in &mut plan.steps {
+            step.src = root_dir.join(&step.src);
+            step.dst = root_dir.join(&step.dst);
+        }
+
+        plan
+    }
+
+    fn execute(plan: &Plan) {
+        for step in plan.steps() {
+            assert_ok!(transfer(
+                &step.src,
+                &step.dst,
+                step.mode,
+                &TransferOptions::default(),
+                &mut Vec::new()
+            ));
+        }
+    }
+
+    fn lines<'a>(plan: &'a Plan, root_dir: &TempDir) -> Vec<(usize, &'a str, &'a str)> {
+        let strip = |path: &'a PathBuf| {
+            let path = assert_ok!(path.strip_prefix(root_dir.path()));
+            path.to_str().unwrap_or_default()
+        };
+
+        plan.steps()
+            .iter()
+            .map(|step| (step.line, strip(&step.src), strip(&step.dst)))
+            .collect()
+    }
+}
@@ -0,0 +1,560 @@
+use crate::fs::backup_path;
+use crate::fs::check;
+use crate::fs::check_nested;
+use crate::fs::check_special;
+use crate::fs::exists_error;
+use crate::fs::is_written_through;
+use crate::fs::normalize;
+use crate::fs::numbered_path;
+use crate::fs::Error;
+use crate::fs::ErrorKind;
+use crate::fs::FileType;
+use crate::fs::OverwriteMode;
+use crate::fs::Result;
+use crate::fs::TransferMode;
+use crate::fs::TransferOptions;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::ffi::OsString;
+use std::fs;
+use std::ops::Bound;
+use std::path::Path;
+use std::path::PathBuf;
+
+// Simulated state of a path which was modified during simulation.
+#[derive(Clone, Default)]
+struct Entry {
+    // Directory was created during simulation.
+    dir: bool,
+    // Real path the content originates from.
+    origin: Option<PathBuf>,
+}
+
+impl Entry {
+    fn file_type(&self) -> FileType {
+        match (self.dir, &self.origin) {
+            (true, _) => FileType::Dir,
+            (false, Some(origin)) => FileType::from(origin.as_path()),
+            (false, None) => FileType::Unknown,
+        }
+    }
+
+    fn dereferenced_type(&self) -> FileType {
+        match (self.dir, &self.origin) {
+            (false, Some(origin)) => FileType::dereferenced(origin),
+            _ => self.file_type(),
+        }
+    }
+
+    fn resolved_type(&self) -> FileType {
+        match self.dereferenced_type() {
+            FileType::Unknown => self.file_type(),
+            file_type => file_type,
+        }
+    }
+
+    fn is_real(&self, path: &Path) -> bool {
+        !self.dir && self.origin.as_deref() == Some(path)
+    }
+}
+
+#[derive(Default)]
+pub struct Simulator {
+    // Paths are ordered by components, so descendants of a path follow it.
+    entries: BTreeMap<PathBuf, Entry>,
+}
+
+impl Simulator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn transfer(
+        &mut self,
+        src: &Path,
+        dst: &Path,
+        mode: TransferMode,
+        options: &TransferOptions,
+    ) -> Result<()> {
+        let src_path = normalize(src);
+        let dst_path = normalize(dst);
+
+        let src_entry = self.resolve(&src_path);
+        let dst_entry = self.resolve(&dst_path);
+        let src_type = if options.dereferences(mode) {
+            src_entry.dereferenced_type()
+        } else {
+            src_entry.file_type()
+        };
+        let dst_type = dst_entry.resolved_type();
+
+        check(src, &src_type, dst, &dst_type)?;
+        check_special(src, &src_type, mode, options)?;
+        check_nested(src, &src_type, dst, mode, options)?;
+
+        let dst_path = match &dst_entry.origin {
+            Some(origin) if is_written_through(&src_type, origin, &dst_type, mode) => {
+                fs::canonicalize(origin)?
+            }
+            _ => dst_path,
+        };
+
+        if src_path == dst_path
+            || (src_entry.is_real(&src_path)
+                && dst_entry.is_real(&dst_path)
+                && options.is_same_file(&src_path, &dst_path, mode)?)
+        {
+            return Ok(());
+        }
+
+        match src_type {
+            FileType::File | FileType::Symlink | FileType::Special(_) => {
+                let dst_path = match dst_type {
+                    FileType::Unknown => dst_path,
+                    _ => match options.overwrite {
+                        OverwriteMode::Never => return Err(exists_error(dst)),
+                        OverwriteMode::Skip => return Ok(()),
+                        OverwriteMode::Always => dst_path,
+                        OverwriteMode::Backup => {
+                            let mut backup_options = options.clone();
+                            backup_options.overwrite = OverwriteMode::Always;
+                            self.transfer(
+                                dst,
+                                &backup_path(dst),
+                                TransferMode::Move,
+                                &backup_options,
+                            )?;
+                            dst_path
+                        }
+                        OverwriteMode::Rename => numbered_path(&dst_path, |path| {
+                            self.resolve(path).file_type() != FileType::Unknown
+                        }),
+                    },
+                };
+
+                if let Some(dst_parent) = dst_path.parent() {
+                    self.create_dir_all(dst_parent)?;
+                }
+
+                self.replace(&src_path, &dst_path, mode);
+                Ok(())
+            }
+
+            FileType::Dir => {
+                if dst_type == FileType::Unknown {
+                    if let Some(dst_parent) = dst_path.parent() {
+                        self.create_dir_all(dst_parent)?;
+                    }
+                    self.replace(&src_path, &dst_path, mode);
+                    return Ok(());
+                }
+
+                for name in self.read_dir_names(&src_path) {
+                    self.transfer(&src.join(&name), &dst.join(&name), mode, options)?;
+                }
+
+                if mode == TransferMode::Move && self.read_dir_names(&src_path).is_empty() {
+                    self.entries.insert(src_path, Entry::default());
+                }
+                Ok(())
+            }
+
+            FileType::Unknown => unreachable!("Expected source path to be checked"),
+        }
+    }
+
+    fn replace(&mut self, src_path: &Path, dst_path: &Path, mode: TransferMode) {
+        let mut updates = vec![(dst_path.to_path_buf(), self.resolve(src_path))];
+
+        for (path, entry) in self.descendants(src_path) {
+            if let Some(rest) = strip_descendant(path, src_path) {
+                updates.push((dst_path.join(rest), entry.clone()));
+            }
+        }
+
+        self.remove_descendants(dst_path);
+
+        if mode == TransferMode::Move {
+            self.remove_descendants(src_path);
+            self.entries
+                .insert(src_path.to_path_buf(), Entry::default());
+        }
+
+        self.entries.extend(updates);
+    }
+
+    fn descendants<'a>(&'a self, path: &'a Path) -> impl Iterator<Item = (&'a PathBuf, &'a Entry)> {
+        self.entries
+            .range::<Path, _>((Bound::Excluded(path), Bound::Unbounded))
+            .take_while(move |(entry_path, _)| entry_path.starts_with(path))
+    }
+
+    fn remove_descendants(&mut self, path: &Path) {
+        let paths = self
+            .descendants(path)
+            .map(|(entry_path, _)| entry_path.clone())
+            .collect::<Vec<_>>();
+
+        for entry_path in paths {
+            self.entries.remove(&entry_path);
+        }
+    }
+
+    fn create_dir_all(&mut self, path: &Path) -> Result<()> {
+        let mut ancestors = path.ancestors().collect::<Vec<_>>();
+        ancestors.reverse();
+
+        for ancestor in ancestors {
+            // Symbolic links to directories are followed as path components.
+            match self.resolve(ancestor).dereferenced_type() {
+                FileType::Dir => {}
+                FileType::File | FileType::Symlink | FileType::Special(_) => {
+                    return Err(Error::new(
+                        ErrorKind::Other,
+                        &format!(
+                            "Cannot create directory '{}', path '{}' is a file",
+                            path.to_string_lossy(),
+                            ancestor.to_string_lossy()
+                        ),
+                    ))
+                }
+                FileType::Unknown => {
+                    let entry = Entry {
+                        dir: true,
+                        origin: None,
+                    };
+                    self.entries.insert(ancestor.to_path_buf(), entry);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn read_dir_names(&self, path: &Path) -> Vec<OsString> {
+        let mut names = BTreeSet::new();
+
+        if let Some(origin) = self.resolve(path).origin {
+            if let Ok(entries) = fs::read_dir(origin) {
+                for entry in entries.flatten() {
+                    names.insert(entry.file_name());
+                }
+            }
+        }
+
+        for (entry_path, _) in self.descendants(path) {
+            if entry_path.parent() == Some(path) {
+                if let Some(name) = entry_path.file_name() {
+                    names.insert(name.to_os_string());
+                }
+            }
+        }
+
+        names
+            .into_iter()
+            .filter(|name| self.resolve(&path.join(name)).file_type() != FileType::Unknown)
+            .collect()
+    }
+
+    fn resolve(&self, path: &Path) -> Entry {
+        for ancestor in path.ancestors() {
+            if let Some(entry) = self.entries.get(ancestor) {
+                return match strip_descendant(path, ancestor) {
+                    Some(rest) => Entry {
+                        dir: false,
+                        origin: entry
+                            .origin
+                            .as_ref()
+                            .map(|origin| origin.join(rest))
+                            .filter(|origin| FileType::from(origin.as_path()) != FileType::Unknown),
+                    },
+                    None => entry.clone(),
+                };
+            }
+        }
+
+        if FileType::from(path) == FileType::Unknown {
+            Entry::default()
+        } else {
+            Entry {
+                dir: false,
+                origin: Some(path.to_path_buf()),
+            }
+        }
+    }
+}
+
+fn strip_descendant<'a>(path: &'a Path, ancestor: &Path) -> Option<&'a Path> {
+    match path.strip_prefix(ancestor) {
+        Ok(rest) if rest.as_os_str().is_empty() => None,
+        Ok(rest) => Some(rest),
+        Err(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::mkdir;
+    use crate::testing::temp_dir;
+    use crate::testing::touch;
+    use assert_fs::prelude::*;
+    use claim::assert_err;
+    use claim::assert_ok;
+
+    #[test]
+    fn move_file() {
+        let root_dir = temp_dir();
+        let src_file = touch(root_dir.child("a"));
+        let dst_file = root_dir.child("b/c");
+
+        let mut simulator = Simulator::new();
+        assert_ok!(simulator.transfer(
+            src_file.path(),
+            dst_file.path(),
+            TransferMode::Move,
+            &TransferOptions::default()
+        ));
+
+        assert_eq!(file_type(&simulator, src_file.path()), FileType::Unknown);
+        assert_eq!(file_type(&simulator, dst_file.path()), FileType::File);
+        assert_eq!(file_type(&simulator, &root_dir.join("b")), FileType::Dir);
+
+        src_file.assert(predicates::path::is_file());
+        dst_file.assert(predicates::path::missing());
+    }
+
+    #[test]
+    fn copy_file() {
+        let root_dir = temp_dir();
+        let src_file = touch(root_dir.child("a"));
+        let dst_file = root_dir.child("b");
+
+        let mut simulator = Simulator::new();
+        assert_ok!(simulator.transfer(
+            src_file.path(),
+            dst_file.path(),
+            TransferMode::Copy,
+            &TransferOptions::default()
+        ));
+
+        assert_eq!(file_type(&simulator, src_file.path()), FileType::File);
+        assert_eq!(file_type(&simulator, dst_file.path()), FileType::File);
+
+        dst_file.assert(predicates::path::missing());
+    }
+
+    #[test]
+    fn move_file_to_itself() {
+        let root_dir = temp_dir();
+        let src_file = touch(root_dir.child("a"));
+
+        let mut simulator = Simulator::new();
+        assert_ok!(simulator.transfer(
+            src_file.path(),
+            src_file.path(),
+            TransferMode::Move,
+            &TransferOptions::default()
+        ));
+
+        assert_eq!(file_type(&simulator, src_file.path()), FileType::File);
+    }
+
+    #[test]
+    fn move_moved_file() {
+        let root_dir = temp_dir();
+        let src_file = touch(root_dir.child("a"));
+        let tmp_file = root_dir.child("b");
+        let dst_file = root_dir.child("c");
+
+        let mut simulator = Simulator::new();
+        assert_ok!(simulator.transfer(
+            src_file.path(),
+            tmp_file.path(),
+            TransferMode::Move,
+            &TransferOptions::default()
+        ));
+        assert_ok!(simulator.transfer(
+            tmp_file.path(),
+            dst_file.path(),
+            TransferMode::Move,
+            &TransferOptions::default()
+        ));
+        assert_err!(simulator.transfer(
+            src_file.path(),
+            dst_file.path(),
+            TransferMode::Move,
+            &TransferOptions::default()
+        ));
+
+        assert_eq!(file_type(&simulator, tmp_file.path()), FileType::Unknown);
+        assert_eq!(file_type(&simulator, dst_file.path()), FileType::File);
+    }
+
+    #[test]
+    fn move_dir_content() {
+        let root_dir = temp_dir();
+        let src_dir = mkdir(root_dir.child("a"));
+        let src_file = touch(src_dir.child("c"));
+        let dst_dir = root_dir.child("b");
+        let dst_file = dst_dir.child("c");
+
+        let mut simulator = Simulator::new();
+        assert_ok!(simulator.transfer(
+            src_file.path(),
+            &src_dir.join("d"),
+            TransferMode::Move,
+            &TransferOptions::default()
+        ));
+        assert_ok!(simulator.transfer(
+            src_dir.path(),
+            dst_dir.path(),
+            TransferMode::Move,
+            &TransferOptions::default()
+        ));
+
+        assert_eq!(file_type(&simulator, src_dir.path()), FileType::Unknown);
+        assert_eq!(file_type(&simulator, dst_dir.path()), FileType::Dir);
+        assert_eq!(file_type(&simulator, dst_file.path()), FileType::Unknown);
+        assert_eq!(file_type(&simulator, &dst_dir.join("d")), FileType::File);
+    }
+
+    #[test]
+    fn move_dir_with_sibling_prefix() {
+        let root_dir = temp_dir();
+        let src_dir = mkdir(root_dir.child("a"));
+        let src_file = touch(root_dir.child("c"));
+        let other_file = touch(root_dir.child("d"));
+        let sibling_file = root_dir.child("a.b");
+        let dst_dir = root_dir.child("e");
+
+        let mut simulator = Simulator::new();
+        assert_ok!(simulator.transfer(
+            src_file.path(),
+            &src_dir.join("f"),
+            TransferMode::Move,
+            &TransferOptions::default()
+        ));
+        assert_ok!(simulator.transfer(
+            other_file.path(),
+            sibling_file.path(),
+            TransferMode::Move,
+            &TransferOptions::default()
+        ));
+        assert_ok!(simulator.transfer(
+            src_dir.path(),
+            dst_dir.path(),
+            TransferMode::Move,
+            &TransferOptions::default()
+        ));
+
+        assert_eq!(file_type(&simulator, &dst_dir.join("f")), FileType::File);
+        assert_eq!(file_type(&simulator, &src_dir.join("f")), FileType::Unknown);
+        assert_eq!(file_type(&simulator, sibling_file.path()), FileType::File);
+        assert_eq!(
+            file_type(&simulator, &dst_dir.join("a.b")),
+            FileType::Unknown
+        );
+    }
+
+    #[test]
+    fn merge_dirs() {
+        let root_dir = temp_dir();
+        let src_dir = mkdir(root_dir.child("a"));
+        touch(src_dir.child("c"));
+        let dst_dir = mkdir(root_dir.child("b"));
+        touch(dst_dir.child("d"));
+
+        let mut simulator = Simulator::new();
+        assert_ok!(simulator.transfer(
+            src_dir.path(),
+            dst_dir.path(),
+            TransferMode::Copy,
+            &TransferOptions::default()
+        ));
+
+        assert_eq!(file_type(&simulator, &dst_dir.join("c")), FileType::File);
+        assert_eq!(file_type(&simulator, &dst_dir.join("d")), FileType::File);
+    }
+
+    #[test]
+    fn overwrite_modes() {
+        let root_dir = temp_dir();
+        let src_file = touch(root_dir.child("a"));
+        let dst_file = touch(root_dir.child("b.c"));
+
+        let mut simulator = Simulator::new();
+        let options = |overwrite| TransferOptions {
+            overwrite,
+            ..TransferOptions::default()
+        };
+
+        let error = assert_err!(simulator.transfer(
+            src_file.path(),
+            dst_file.path(),
+            TransferMode::Copy,
+            &options(OverwriteMode::Never)
+        ));
+        assert_eq!(format!("{:?}", error.kind), "AlreadyExists");
+
+        assert_ok!(simulator.transfer(
+            src_file.path(),
+            dst_file.path(),
+            TransferMode::Move,
+            &options(OverwriteMode::Skip)
+        ));
+        assert_eq!(file_type(&simulator, src_file.path()), FileType::File);
+
+        assert_ok!(simulator.transfer(
+            src_file.path(),
+            dst_file.path(),
+            TransferMode::Copy,
+            &options(OverwriteMode::Rename)
+        ));
+        assert_eq!(
+            file_type(&simulator, &root_dir.join("b (1).c")),
+            FileType::File
+        );
+
+        assert_ok!(simulator.transfer(
+            src_file.path(),
+            dst_file.path(),
+            TransferMode::Move,
+            &options(OverwriteMode::Backup)
+        ));
+        assert_eq!(file_type(&simulator, src_file.path()), FileType::Unknown);
+        assert_eq!(file_type(&simulator, dst_file.path()), FileType::File);
+        assert_eq!(
+            file_type(&simulator, &root_dir.join("b.c~")),
+            FileType::File
+        );
+    }
+
+    #[test]
+    fn create_dir_in_file() {
+        let root_dir = temp_dir();
+        let src_file = touch(root_dir.child("a"));
+        let dst_file = touch(root_dir.child("b"));
+
+        let mut simulator = Simulator::new();
+        let error = assert_err!(simulator.transfer(
+            src_file.path(),
+            &dst_file.join("c"),
+            TransferMode::Move,
+            &TransferOptions::default()
+        ));
+
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "Cannot create directory '{}', path '{}' is a file",
+                dst_file.path().to_string_lossy(),
+                dst_file.path().to_string_lossy()
+            )
+        );
+    }
+
+    fn file_type(simulator: &Simulator, path: &Path) -> FileType {
+        simulator.resolve(&normalize(path)).file_type()
+    }
+}
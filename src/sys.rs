@@ -0,0 +1,733 @@
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+use std::path::Path;
+
+// Size of blocks checked for zeros when creating holes.
+const BLOCK_SIZE: usize = 4096;
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::ffi::CStr;
+    use std::ffi::CString;
+    use std::fs::File;
+    use std::io;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::io::AsRawFd;
+    use std::path::Path;
+
+    // Not provided by the libc crate, equals to `_IOW(0x94, 9, int)`.
+    const FICLONE: libc::c_ulong = 0x40049409;
+
+    // Maximal number of bytes transferred by `sendfile` in a single call.
+    const MAX_CHUNK: usize = 0x7ffff000;
+
+    #[cfg(test)]
+    pub fn c_name(name: &str) -> CString {
+        CString::new(name).expect("Expected name without NUL")
+    }
+
+    pub fn c_path(path: &Path) -> io::Result<CString> {
+        CString::new(path.as_os_str().as_bytes())
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))
+    }
+
+    pub fn renameat2(src: &Path, dst: &Path, flags: libc::c_uint) -> io::Result<()> {
+        let src = c_path(src)?;
+        let dst = c_path(dst)?;
+
+        // Called directly, because older C libraries do not provide a wrapper.
+        let result = unsafe {
+            libc::syscall(
+                libc::SYS_renameat2,
+                libc::AT_FDCWD,
+                src.as_ptr(),
+                libc::AT_FDCWD,
+                dst.as_ptr(),
+                flags,
+            )
+        };
+
+        if result == 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+
+    pub fn ficlone(src: &File, dst: &File) -> io::Result<()> {
+        let result = unsafe { libc::ioctl(dst.as_raw_fd(), FICLONE as _, src.as_raw_fd()) };
+
+        if result == 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+
+    pub fn copy_file_range(src: &File, dst: &File) -> io::Result<usize> {
+        // Called directly, because older C libraries do not provide a wrapper.
+        let result = unsafe {
+            libc::syscall(
+                libc::SYS_copy_file_range,
+                src.as_raw_fd(),
+                std::ptr::null_mut::<libc::loff_t>(),
+                dst.as_raw_fd(),
+                std::ptr::null_mut::<libc::loff_t>(),
+                MAX_CHUNK,
+                0,
+            )
+        };
+
+        if result >= 0 {
+            Ok(result as usize)
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+
+    pub fn sendfile(src: &File, dst: &File) -> io::Result<usize> {
+        let result = unsafe {
+            libc::sendfile(
+                dst.as_raw_fd(),
+                src.as_raw_fd(),
+                std::ptr::null_mut(),
+                MAX_CHUNK,
+            )
+        };
+
+        if result >= 0 {
+            Ok(result as usize)
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+
+    // Returns offset of the next data/hole, `None` when there is no more data.
+    pub fn seek(file: &File, offset: u64, whence: libc::c_int) -> io::Result<Option<u64>> {
+        let result = unsafe { libc::lseek64(file.as_raw_fd(), offset as libc::off64_t, whence) };
+
+        if result >= 0 {
+            Ok(Some(result as u64))
+        } else {
+            match io::Error::last_os_error() {
+                error if error.raw_os_error() == Some(libc::ENXIO) => Ok(None),
+                error => Err(error),
+            }
+        }
+    }
+
+    pub fn list_xattrs(file: &File) -> io::Result<Vec<CString>> {
+        let fd = file.as_raw_fd();
+        let size = check_size(unsafe { libc::flistxattr(fd, std::ptr::null_mut(), 0) })?;
+        let mut buffer = vec![0u8; size];
+        let size = check_size(unsafe {
+            libc::flistxattr(fd, buffer.as_mut_ptr() as *mut libc::c_char, buffer.len())
+        })?;
+
+        buffer.truncate(size);
+        Ok(buffer
+            .split(|&byte| byte == 0)
+            .filter(|name| !name.is_empty())
+            .map(|name| CString::new(name).expect("Expected name without NUL"))
+            .collect())
+    }
+
+    pub fn get_xattr(file: &File, name: &CStr) -> io::Result<Option<Vec<u8>>> {
+        let fd = file.as_raw_fd();
+        let size = match check_size(unsafe {
+            libc::fgetxattr(fd, name.as_ptr(), std::ptr::null_mut(), 0)
+        }) {
+            Ok(size) => size,
+            Err(error) if error.raw_os_error() == Some(libc::ENODATA) => return Ok(None),
+            Err(error) => return Err(error),
+        };
+        let mut buffer = vec![0u8; size];
+        let size = check_size(unsafe {
+            libc::fgetxattr(
+                fd,
+                name.as_ptr(),
+                buffer.as_mut_ptr() as *mut libc::c_void,
+                buffer.len(),
+            )
+        })?;
+
+        buffer.truncate(size);
+        Ok(Some(buffer))
+    }
+
+    pub fn set_xattr(file: &File, name: &CStr, value: &[u8]) -> io::Result<()> {
+        let result = unsafe {
+            libc::fsetxattr(
+                file.as_raw_fd(),
+                name.as_ptr(),
+                value.as_ptr() as *const libc::c_void,
+                value.len(),
+                0,
+            )
+        };
+
+        if result == 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+
+    fn check_size(result: libc::ssize_t) -> io::Result<usize> {
+        if result >= 0 {
+            Ok(result as usize)
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+
+    pub fn is_unsupported(error: &io::Error) -> bool {
+        matches!(error.raw_os_error(), Some(libc::EINVAL | libc::ENOSYS))
+    }
+
+    pub fn is_unsupported_copy(error: &io::Error) -> bool {
+        matches!(
+            error.raw_os_error(),
+            Some(libc::EINVAL | libc::ENOSYS | libc::EXDEV | libc::EOPNOTSUPP | libc::EPERM)
+        )
+    }
+}
+
+// Atomically renames path, unless the destination already exists.
+pub fn rename_noreplace(src: &Path, dst: &Path) -> io::Result<()> {
+    #[cfg(target_os = "linux")]
+    match linux::renameat2(src, dst, libc::RENAME_NOREPLACE) {
+        Err(error) if linux::is_unsupported(&error) => {}
+        result => return result,
+    }
+
+    rename_noreplace_fallback(src, dst)
+}
+
+// Shares content of the source file with the destination file using copy-on-write.
+pub fn reflink(src: &File, dst: &File) -> io::Result<()> {
+    #[cfg(target_os = "linux")]
+    return linux::ficlone(src, dst);
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = (src, dst);
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Reflinks are not supported on this platform",
+        ))
+    }
+}
+
+// Copies the rest of the source file using in-kernel copying when possible.
+// Both files keep their position, so each method continues where the previous one failed.
+pub fn copy_content(src: &mut File, dst: &mut File) -> io::Result<()> {
+    #[cfg(target_os = "linux")]
+    for copy in [linux::copy_file_range, linux::sendfile] {
+        match copy_chunks(src, dst, copy) {
+            Err(error) if linux::is_unsupported_copy(&error) => {}
+            result => return result,
+        }
+    }
+
+    io::copy(src, dst).map(|_| ())
+}
+
+#[cfg(target_os = "linux")]
+fn copy_chunks(
+    src: &File,
+    dst: &File,
+    copy: fn(&File, &File) -> io::Result<usize>,
+) -> io::Result<()> {
+    // Some file systems (e.g. procfs) report zero size and copy nothing.
+    if copy(src, dst)? == 0 {
+        return Err(io::Error::from_raw_os_error(libc::EINVAL));
+    }
+    while copy(src, dst)? > 0 {}
+    Ok(())
+}
+
+// Estimates whether file has holes, like GNU cp does.
+pub fn is_sparse(file: &File) -> io::Result<bool> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let metadata = file.metadata()?;
+        Ok(metadata.blocks() * 512 < metadata.len())
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = file;
+        Ok(false)
+    }
+}
+
+// Copies the source file while keeping its holes in the destination file.
+// With `zeros` enabled, holes are also created for blocks of zeros.
+pub fn copy_sparse(src: &mut File, dst: &mut File, zeros: bool) -> io::Result<()> {
+    let len = src.metadata()?.len();
+
+    #[cfg(not(target_os = "linux"))]
+    let _ = zeros;
+    #[cfg(target_os = "linux")]
+    match copy_data_segments(src, dst, len, zeros) {
+        Err(error) if linux::is_unsupported(&error) => {
+            src.rewind()?;
+            dst.rewind()?;
+        }
+        result => return result.and_then(|()| dst.set_len(len)),
+    }
+
+    copy_holes(src, dst, len)?;
+    dst.set_len(len)
+}
+
+#[cfg(target_os = "linux")]
+fn copy_data_segments(src: &mut File, dst: &mut File, len: u64, zeros: bool) -> io::Result<()> {
+    let mut offset = 0;
+
+    while offset < len {
+        let start = match linux::seek(src, offset, libc::SEEK_DATA)? {
+            Some(start) => start,
+            None => break,
+        };
+        let end = match linux::seek(src, start, libc::SEEK_HOLE)? {
+            Some(end) => end.min(len),
+            None => len,
+        };
+
+        src.seek(SeekFrom::Start(start))?;
+        dst.seek(SeekFrom::Start(start))?;
+
+        if zeros {
+            copy_holes(src, dst, end - start)?;
+        } else {
+            io::copy(&mut Read::by_ref(src).take(end - start), dst)?;
+        }
+
+        offset = end;
+    }
+
+    Ok(())
+}
+
+fn copy_holes(src: &mut File, dst: &mut File, len: u64) -> io::Result<()> {
+    let mut buffer = vec![0; 16 * BLOCK_SIZE];
+    let mut remaining = len;
+
+    while remaining > 0 {
+        let size = remaining.min(buffer.len() as u64) as usize;
+        let read = src.read(&mut buffer[..size])?;
+
+        if read == 0 {
+            break;
+        }
+
+        for block in buffer[..read].chunks(BLOCK_SIZE) {
+            if block.iter().all(|&byte| byte == 0) {
+                dst.seek(SeekFrom::Current(block.len() as i64))?;
+            } else {
+                dst.write_all(block)?;
+            }
+        }
+
+        remaining -= read as u64;
+    }
+
+    Ok(())
+}
+
+pub fn set_owner(file: &File, metadata: &fs::Metadata) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        std::os::unix::fs::fchown(file, Some(metadata.uid()), Some(metadata.gid()))
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (file, metadata);
+        Ok(())
+    }
+}
+
+// Makes creation, rename or removal of entries inside the directory durable.
+pub fn sync_dir(path: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        // Parent of a relative path with a single component is empty.
+        let path = if path.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            path
+        };
+        File::open(path)?.sync_all()
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = path;
+        Ok(())
+    }
+}
+
+// Opens directory, so its attributes can be read or changed.
+pub fn open_dir(path: &Path, write: bool) -> io::Result<File> {
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::OpenOptionsExt;
+
+        // Not provided by the standard library.
+        const FILE_READ_ATTRIBUTES: u32 = 0x80;
+        const FILE_WRITE_ATTRIBUTES: u32 = 0x100;
+        const FILE_FLAG_BACKUP_SEMANTICS: u32 = 0x02000000;
+
+        // Directories cannot be opened without backup semantics.
+        let access = if write {
+            FILE_READ_ATTRIBUTES | FILE_WRITE_ATTRIBUTES
+        } else {
+            FILE_READ_ATTRIBUTES
+        };
+        fs::OpenOptions::new()
+            .access_mode(access)
+            .custom_flags(FILE_FLAG_BACKUP_SEMANTICS)
+            .open(path)
+    }
+    #[cfg(not(windows))]
+    {
+        // Attributes are changed through a read-only descriptor.
+        let _ = write;
+        File::open(path)
+    }
+}
+
+// Evicts cached content of the file, so it is read again from the disk.
+pub fn drop_cache(file: &File) -> io::Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::unix::io::AsRawFd;
+        let result =
+            unsafe { libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED) };
+
+        if result == 0 {
+            Ok(())
+        } else {
+            Err(io::Error::from_raw_os_error(result))
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = file;
+        Ok(())
+    }
+}
+
+pub fn link_count(metadata: &fs::Metadata) -> u64 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        metadata.nlink()
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        1
+    }
+}
+
+// Special files are not opened, because it may block or have side effects.
+pub fn set_node_owner(path: &Path, metadata: &fs::Metadata) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        std::os::unix::fs::chown(path, Some(metadata.uid()), Some(metadata.gid()))
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (path, metadata);
+        Ok(())
+    }
+}
+
+// Creates a named pipe, socket or device node of the same type as the original one.
+pub fn make_node(path: &Path, metadata: &fs::Metadata) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::ffi::CString;
+        use std::os::unix::ffi::OsStrExt;
+        use std::os::unix::fs::FileTypeExt;
+        use std::os::unix::fs::MetadataExt;
+
+        let c_path = CString::new(path.as_os_str().as_bytes())
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))?;
+        let mode = metadata.mode() as libc::mode_t;
+
+        let result = if metadata.file_type().is_fifo() {
+            unsafe { libc::mkfifo(c_path.as_ptr(), mode & 0o7777) }
+        } else {
+            unsafe { libc::mknod(c_path.as_ptr(), mode, metadata.rdev() as libc::dev_t) }
+        };
+
+        if result == 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!(
+                "Cannot create special file '{}' on this platform",
+                path.to_string_lossy()
+            ),
+        ))
+    }
+}
+
+// Copies either ACLs or other extended attributes.
+pub fn copy_xattrs(src: &File, dst: &File, acl: bool) -> io::Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        // POSIX ACLs are stored as extended attributes with these names.
+        const ACL_PREFIX: &[u8] = b"system.posix_acl_";
+
+        for name in linux::list_xattrs(src)? {
+            if name.as_bytes().starts_with(ACL_PREFIX) != acl {
+                continue;
+            }
+            if let Some(value) = linux::get_xattr(src, &name)? {
+                linux::set_xattr(dst, &name, &value)?;
+            }
+        }
+        Ok(())
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = (src, dst, acl);
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Extended attributes are not supported on this platform",
+        ))
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+pub fn get_xattr(file: &File, name: &str) -> io::Result<Option<Vec<u8>>> {
+    linux::get_xattr(file, &linux::c_name(name))
+}
+
+#[cfg(all(test, target_os = "linux"))]
+pub fn set_xattr(file: &File, name: &str, value: &[u8]) -> io::Result<()> {
+    linux::set_xattr(file, &linux::c_name(name), value)
+}
+
+pub const HAS_SYMLINKS: bool = cfg!(any(unix, windows));
+
+// Windows distinguishes links to files and directories, so the type is taken
+// from the target. Relative target is resolved from the directory of the link.
+pub fn symlink(target: &Path, link: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    return std::os::unix::fs::symlink(target, link);
+    #[cfg(windows)]
+    {
+        let parent = link.parent().unwrap_or(Path::new(""));
+
+        if parent.join(target).is_dir() {
+            std::os::windows::fs::symlink_dir(target, link)
+        } else {
+            std::os::windows::fs::symlink_file(target, link)
+        }
+    }
+    #[cfg(not(any(unix, windows)))]
+    return Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!(
+            "Cannot create symbolic link '{}' on this platform",
+            link.to_string_lossy()
+        ),
+    ));
+}
+
+// Recreates symbolic link with the same target. On Windows, the link type is taken
+// from the original link, because its relative target may not exist next to the new one.
+pub fn copy_symlink(original: &Path, target: &Path, link: &Path) -> io::Result<()> {
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::FileTypeExt;
+
+        if original.symlink_metadata()?.file_type().is_symlink_dir() {
+            std::os::windows::fs::symlink_dir(target, link)
+        } else {
+            std::os::windows::fs::symlink_file(target, link)
+        }
+    }
+    #[cfg(not(windows))]
+    {
+        let _ = original;
+        symlink(target, link)
+    }
+}
+
+// Matches EXDEV on Unix and ERROR_NOT_SAME_DEVICE on Windows.
+pub fn is_cross_device(error: &io::Error) -> bool {
+    error.kind() == io::ErrorKind::CrossesDevices
+}
+
+// Device and inode number identify a file without opening it,
+// which could block (named pipes) or follow symbolic links.
+pub fn file_id(metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        Some((metadata.dev(), metadata.ino()))
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        None
+    }
+}
+
+fn rename_noreplace_fallback(src: &Path, dst: &Path) -> io::Result<()> {
+    if src.symlink_metadata()?.is_dir() {
+        // Creating directory fails when it already exists.
+        // Renaming directory then replaces the empty one we own.
+        fs::create_dir(dst)?;
+        fs::rename(src, dst).inspect_err(|_| {
+            fs::remove_dir(dst).ok();
+        })
+    } else {
+        // Creating link fails when the destination already exists.
+        fs::hard_link(src, dst)?;
+        fs::remove_file(src)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::temp_dir;
+    use assert_fs::prelude::*;
+    use assert_fs::NamedTempFile;
+    use claim::assert_err;
+    use claim::assert_ok;
+    use test_case::test_case;
+
+    #[test_case(rename_noreplace          ; "native")]
+    #[test_case(rename_noreplace_fallback ; "fallback")]
+    fn rename_file(rename: fn(&Path, &Path) -> io::Result<()>) {
+        let root_dir = temp_dir();
+        let src_file = root_dir.child("a");
+        let dst_file = root_dir.child("b");
+        let other_file = root_dir.child("c");
+
+        assert_ok!(src_file.write_str("1"));
+        assert_ok!(other_file.write_str("2"));
+
+        let error = assert_err!(rename(src_file.path(), other_file.path()));
+        assert_eq!(error.kind(), io::ErrorKind::AlreadyExists);
+        assert_ok!(rename(src_file.path(), dst_file.path()));
+
+        src_file.assert(predicates::path::missing());
+        dst_file.assert("1");
+        other_file.assert("2");
+    }
+
+    #[test_case(rename_noreplace          ; "native")]
+    #[test_case(rename_noreplace_fallback ; "fallback")]
+    fn rename_dir(rename: fn(&Path, &Path) -> io::Result<()>) {
+        let root_dir = temp_dir();
+        let src_dir = root_dir.child("a");
+        let dst_dir = root_dir.child("b");
+        let other_dir = root_dir.child("c");
+
+        assert_ok!(src_dir.child("d").write_str("1"));
+        assert_ok!(other_dir.create_dir_all());
+
+        let error = assert_err!(rename(src_dir.path(), other_dir.path()));
+        assert_eq!(error.kind(), io::ErrorKind::AlreadyExists);
+        assert_ok!(rename(src_dir.path(), dst_dir.path()));
+
+        src_dir.assert(predicates::path::missing());
+        dst_dir.child("d").assert("1");
+        other_dir.assert(predicates::path::is_dir());
+    }
+
+    #[test]
+    fn cross_device_error() {
+        #[cfg(unix)]
+        let code = libc::EXDEV;
+        #[cfg(windows)]
+        let code = 17; // ERROR_NOT_SAME_DEVICE
+
+        #[cfg(any(unix, windows))]
+        assert!(is_cross_device(&io::Error::from_raw_os_error(code)));
+        assert!(!is_cross_device(&io::Error::from(io::ErrorKind::NotFound)));
+    }
+
+    #[test]
+    fn copy_file_content() {
+        let (src_file, dst_file) = copy_files();
+        let mut src = assert_ok!(File::open(src_file.path()));
+        let mut dst = assert_ok!(File::create(dst_file.path()));
+
+        assert_ok!(copy_content(&mut src, &mut dst));
+        dst_file.assert(content().as_str());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test_case(linux::copy_file_range ; "copy_file_range")]
+    #[test_case(linux::sendfile        ; "sendfile")]
+    fn copy_file_chunks(copy: fn(&File, &File) -> io::Result<usize>) {
+        let (src_file, dst_file) = copy_files();
+        let src = assert_ok!(File::open(src_file.path()));
+        let dst = assert_ok!(File::create(dst_file.path()));
+
+        assert_ok!(copy_chunks(&src, &dst, copy));
+        dst_file.assert(content().as_str());
+    }
+
+    #[cfg(unix)]
+    #[test_case(false ; "holes")]
+    #[test_case(true  ; "zeros")]
+    fn copy_sparse_file(zeros: bool) {
+        use std::os::unix::fs::MetadataExt;
+
+        let (src_file, dst_file) = copy_files();
+        let mut src = assert_ok!(File::options().read(true).write(true).open(src_file.path()));
+        assert_ok!(src.set_len(10_000_000));
+        assert_ok!(src.seek(SeekFrom::Start(9_000_000)));
+        assert_ok!(src.write_all(&[0; BLOCK_SIZE]));
+        assert_ok!(src.write_all(b"end"));
+        assert_ok!(src.rewind());
+
+        let mut dst = assert_ok!(File::create(dst_file.path()));
+        assert_ok!(copy_sparse(&mut src, &mut dst, zeros));
+
+        let src_metadata = assert_ok!(src.metadata());
+        let dst_metadata = assert_ok!(dst.metadata());
+        assert_eq!(dst_metadata.len(), src_metadata.len());
+        assert!(dst_metadata.blocks() <= src_metadata.blocks());
+        assert!(assert_ok!(is_sparse(&dst)));
+
+        let src_content = assert_ok!(fs::read(src_file.path()));
+        let dst_content = assert_ok!(fs::read(dst_file.path()));
+        assert!(src_content == dst_content);
+    }
+
+    fn copy_files() -> (NamedTempFile, NamedTempFile) {
+        let src_file = assert_ok!(NamedTempFile::new("a"));
+        let dst_file = assert_ok!(NamedTempFile::new("b"));
+        assert_ok!(src_file.write_str(&content()));
+        (src_file, dst_file)
+    }
+
+    fn content() -> String {
+        "0123456789".repeat(100_000)
+    }
+}
@@ -0,0 +1,27 @@
+use assert_fs::prelude::*;
+use assert_fs::NamedTempFile;
+use assert_fs::TempDir;
+use claim::assert_ok;
+
+pub fn temp_dir() -> TempDir {
+    assert_ok!(TempDir::new())
+}
+
+pub fn temp_file(name: &str) -> NamedTempFile {
+    assert_ok!(NamedTempFile::new(name))
+}
+
+pub fn mkdir<P: PathCreateDir>(path: P) -> P {
+    assert_ok!(path.create_dir_all());
+    path
+}
+
+pub fn touch<F: FileTouch>(file: F) -> F {
+    assert_ok!(file.touch());
+    file
+}
+
+pub fn write<F: FileWriteStr>(file: F, data: &str) -> F {
+    assert_ok!(file.write_str(data));
+    file
+}
@@ -1,3 +1,4 @@
+use crate::fs::Action;
 use crate::TransferMode;
 use once_cell::sync::Lazy;
 use std::io::Result;
@@ -19,19 +20,26 @@ fn color_spec(fg: Color) -> ColorSpec {
 
 pub struct Logger<W> {
     writer: W,
+    dry_run: bool,
 }
 
 impl<W> Logger<W> {
-    pub fn new(writer: W) -> Self {
-        Self { writer }
+    pub fn new(writer: W, dry_run: bool) -> Self {
+        Self { writer, dry_run }
     }
 }
 
 impl<W: WriteColor> Logger<W> {
     pub fn begin(&mut self, src: &Path, dst: &Path, mode: TransferMode) -> Result<()> {
-        let action = match mode {
-            TransferMode::Move => "Moving",
-            TransferMode::Copy => "Copying",
+        let action = match (mode, self.dry_run) {
+            (TransferMode::Move, false) => "Moving",
+            (TransferMode::Copy, false) => "Copying",
+            (TransferMode::Link, false) => "Linking",
+            (TransferMode::Symlink, false) => "Symlinking",
+            (TransferMode::Move, true) => "Would move",
+            (TransferMode::Copy, true) => "Would copy",
+            (TransferMode::Link, true) => "Would link",
+            (TransferMode::Symlink, true) => "Would symlink",
         };
         write!(self.writer, "{} '", action)?;
         self.writer.set_color(&SRC_COLOR)?;
@@ -44,6 +52,37 @@ impl<W: WriteColor> Logger<W> {
         write!(self.writer, "' ... ")
     }
 
+    pub fn begin_revert(&mut self, action: &Action) -> Result<()> {
+        match action {
+            Action::Move(src, dst) | Action::Replace(src, dst) => {
+                write!(self.writer, "Moving '")?;
+                self.writer.set_color(&DST_COLOR)?;
+                write!(self.writer, "{}", dst.to_string_lossy())?;
+                self.writer.reset()?;
+                write!(self.writer, "' back to '")?;
+                self.writer.set_color(&SRC_COLOR)?;
+                write!(self.writer, "{}", src.to_string_lossy())?;
+                self.writer.reset()?;
+            }
+            Action::Copy(_, path)
+            | Action::Link(_, path)
+            | Action::Symlink(_, path)
+            | Action::CreateDir(path) => {
+                write!(self.writer, "Removing '")?;
+                self.writer.set_color(&DST_COLOR)?;
+                write!(self.writer, "{}", path.to_string_lossy())?;
+                self.writer.reset()?;
+            }
+            Action::RemoveDir(path) => {
+                write!(self.writer, "Creating '")?;
+                self.writer.set_color(&SRC_COLOR)?;
+                write!(self.writer, "{}", path.to_string_lossy())?;
+                self.writer.reset()?;
+            }
+        }
+        write!(self.writer, "' ... ")
+    }
+
     pub fn success(&mut self) -> Result<()> {
         self.writer.set_color(&SUCCESS_COLOR)?;
         write!(self.writer, "OK")?;